@@ -0,0 +1,121 @@
+use crate::http;
+use crate::mirror;
+
+/// One comic as listed on the https://xkcd.com/archive/ page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub num: u32,
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub title: String,
+}
+
+pub const ARCHIVE_PATH: &str = "/archive/";
+
+pub fn fetch_archive() -> Result<Vec<ArchiveEntry>, http::Error> {
+    let html = http::read_body(mirror::get(ARCHIVE_PATH)?, "text/html")?;
+    Ok(parse_archive(&String::from_utf8_lossy(&html)))
+}
+
+/// Parses the archive page, whose entries look like
+/// `<a href="/353/" title="2007-12-5">Python</a>`. Entries are returned in
+/// ascending comic number order.
+pub fn parse_archive(html: &str) -> Vec<ArchiveEntry> {
+    let mut entries: Vec<ArchiveEntry> = html
+        .split("<a href=\"/")
+        .skip(1)
+        .filter_map(parse_entry)
+        .collect();
+    entries.sort_by_key(|e| e.num);
+    entries.dedup_by_key(|e| e.num);
+    entries
+}
+
+fn parse_entry(chunk: &str) -> Option<ArchiveEntry> {
+    let (num, rest) = chunk.split_once("/\" title=\"")?;
+    let num = num.parse().ok()?;
+    let (date, rest) = rest.split_once("\">")?;
+    let (title, _) = rest.split_once("</a>")?;
+
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<u32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    Some(ArchiveEntry {
+        num,
+        year,
+        month,
+        day,
+        title: unescape_html(title),
+    })
+}
+
+/// Decodes the named and numeric character references xkcd.com pages use.
+pub fn unescape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => {
+                    let code = match entity.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_archive() {
+        let html = r#"<div id="middleContainer" class="box">
+<a href="/353/" title="2007-12-5">Python</a><br/>
+<a href="/1/" title="2006-1-1">Barrel - Part 1</a><br/>
+<a href="/259/" title="2007-5-4">Exchanges &amp; &#39;Such&#x27;</a><br/>
+<a href="/about/">About</a>
+</div>"#;
+
+        let entries = parse_archive(html);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            ArchiveEntry {
+                num: 1,
+                year: 2006,
+                month: 1,
+                day: 1,
+                title: "Barrel - Part 1".to_string(),
+            }
+        );
+        assert_eq!(entries[1].title, "Exchanges & 'Such'");
+        assert_eq!(entries[2].num, 353);
+        assert_eq!((entries[2].month, entries[2].day), (12, 5));
+    }
+}
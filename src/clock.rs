@@ -0,0 +1,53 @@
+use std::time::{Instant, SystemTime};
+
+/// Where the filesystem gets the current time from, so freshness checks can
+/// be tested by moving a clock forward instead of sleeping.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock(std::sync::Arc<std::sync::Mutex<(Instant, SystemTime)>>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock(std::sync::Arc::new(std::sync::Mutex::new((
+            Instant::now(),
+            SystemTime::now(),
+        ))))
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        let mut now = self.0.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().0
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.0.lock().unwrap().1
+    }
+}
@@ -0,0 +1,309 @@
+use crate::archive::ArchiveEntry;
+use crate::dimensions::image_dimensions;
+use crate::http;
+use crate::mirror;
+use crate::scrape;
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+static MAX_IMAGE_SIZE: OnceLock<u64> = OnceLock::new();
+
+/// Tells apart the temporary files of images spooled by this process.
+static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+/// How much of a spooled image is read back to find its dimensions. Enough
+/// for any PNG or GIF, and for JPEGs without huge metadata up front.
+const HEADER_LEN: u64 = 64 * 1024;
+
+/// Metadata as served by info.0.json. Only `num` is required: text fields
+/// that are missing or null come out empty, and fields this doesn't know,
+/// like `extra_parts` on interactive comics, are ignored.
+#[derive(Clone, Default, Deserialize)]
+pub struct XkcdComic {
+    pub num: u32,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub title: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub safe_title: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub alt: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub img: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub link: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub news: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub year: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub month: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub day: String,
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl XkcdComic {
+    /// Publication date, if the API's year/month/day strings form one.
+    pub fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(
+            self.year.parse().ok()?,
+            self.month.parse().ok()?,
+            self.day.parse().ok()?,
+        )
+    }
+}
+
+/// A comic image as fetched.
+pub enum Image {
+    Buffered(Vec<u8>),
+    /// An image larger than --max-image-size, kept on disk instead.
+    Spooled(SpooledImage),
+}
+
+impl Image {
+    /// The buffered bytes, empty for spooled images, and the spooled image.
+    pub fn into_parts(self) -> (Vec<u8>, Option<SpooledImage>) {
+        match self {
+            Image::Buffered(bytes) => (bytes, None),
+            Image::Spooled(image) => (Vec::new(), Some(image)),
+        }
+    }
+}
+
+/// An image downloaded to a temporary file that is already unlinked, so
+/// it goes away with the daemon, whichever way that exits.
+pub struct SpooledImage {
+    file: File,
+    size: u64,
+    sha256: [u8; 32],
+    dimensions: Option<(u32, u32)>,
+}
+
+impl SpooledImage {
+    fn create() -> io::Result<File> {
+        let path = std::env::temp_dir().join(format!(
+            "xkcdfs-{}-{}.img",
+            std::process::id(),
+            NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+        Ok(file)
+    }
+
+    /// Wraps a file the image has been written to, reading its dimensions
+    /// back from the header.
+    fn new(file: File, size: u64, sha256: [u8; 32]) -> io::Result<Self> {
+        let mut image = SpooledImage {
+            file,
+            size,
+            sha256,
+            dimensions: None,
+        };
+        image.dimensions = image_dimensions(&image.read_at(0, HEADER_LEN)?);
+        Ok(image)
+    }
+
+    #[cfg(test)]
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let file = Self::create().unwrap();
+        file.write_all_at(bytes, 0).unwrap();
+        Self::new(file, bytes.len() as u64, Sha256::digest(bytes).into()).unwrap()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn sha256(&self) -> [u8; 32] {
+        self.sha256
+    }
+
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions
+    }
+
+    /// Reads up to `size` bytes at `offset`, fewer at the end of the image.
+    pub fn read_at(&self, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+        let size = size.min(self.size.saturating_sub(offset));
+        let mut data = vec![0; size as usize];
+        self.file.read_exact_at(&mut data, offset)?;
+        Ok(data)
+    }
+}
+
+/// Where an image goes while it downloads: memory up to `max` bytes, then a
+/// temporary file from the first write that would go over. Hashed along
+/// the way, since a spooled image isn't read again in one piece.
+struct Spool {
+    max: u64,
+    buffer: Vec<u8>,
+    file: Option<File>,
+    hasher: Sha256,
+}
+
+impl Spool {
+    fn new(max: u64) -> Self {
+        Spool {
+            max,
+            buffer: Vec::new(),
+            file: None,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Moves what is buffered so far to a temporary file, which takes all
+    /// further writes.
+    fn spill(&mut self) -> io::Result<()> {
+        let mut file = SpooledImage::create()?;
+        file.write_all(&self.buffer)?;
+        self.buffer = Vec::new();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn into_image(self, size: u64) -> io::Result<Image> {
+        match self.file {
+            Some(file) => Ok(Image::Spooled(SpooledImage::new(
+                file,
+                size,
+                self.hasher.finalize().into(),
+            )?)),
+            None => Ok(Image::Buffered(self.buffer)),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.buffer.len() as u64 + data.len() as u64 > self.max {
+            self.spill()?;
+        }
+        match &mut self.file {
+            Some(file) => file.write_all(data)?,
+            None => self.buffer.extend_from_slice(data),
+        }
+        self.hasher.update(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A comic's metadata together with its downloaded image.
+pub struct Comic {
+    pub info: XkcdComic,
+    pub image: Image,
+    /// Categories from explainxkcd, empty if they couldn't be fetched.
+    pub tags: Vec<String>,
+    /// The whole archive, sent along with the first comic when the
+    /// filesystem was mounted without fetching anything.
+    pub archive: Option<Vec<ArchiveEntry>>,
+}
+
+pub fn fetch_latest_info() -> Result<XkcdComic, http::Error> {
+    fetch_page_info("/")
+}
+
+/// Metadata of comic `num`, for comics other than the latest.
+pub fn fetch_info(num: u32) -> Result<XkcdComic, http::Error> {
+    fetch_page_info(&format!("/{}/", num))
+}
+
+/// Metadata from the JSON API next to the comic page at `page`, or scraped
+/// from the page itself if the API errors or returns something malformed.
+/// The API's error is returned if scraping doesn't work either.
+fn fetch_page_info(page: &str) -> Result<XkcdComic, http::Error> {
+    let json = mirror::get(&format!("{}info.0.json", page));
+    let error = match json.and_then(http::read_json) {
+        Ok(info) => return Ok(info),
+        Err(e) => e,
+    };
+    mirror::get(page)
+        .ok()
+        .and_then(|response| http::read_body(response, "text/html").ok())
+        .and_then(|html| scrape::parse_comic_page(&String::from_utf8_lossy(&html)))
+        .ok_or(error)
+}
+
+/// Keeps images larger than `bytes` out of memory. Has to be called before
+/// the first image is fetched.
+pub fn set_max_image_size(bytes: u64) {
+    let _ = MAX_IMAGE_SIZE.set(bytes);
+}
+
+/// Downloads the image at `url`. Images larger than the --max-image-size
+/// limit are spooled to a temporary file rather than held in memory,
+/// straight away if the server announces the length and otherwise once
+/// that many bytes have arrived. Either way downloads are bounded by
+/// --max-response-size.
+pub fn fetch_image(url: &str) -> Result<Image, http::Error> {
+    let response = http::send(http::client().get(url))?;
+    let Some(&max) = MAX_IMAGE_SIZE.get() else {
+        return Ok(Image::Buffered(http::read_body(response, "image/")?));
+    };
+    let mut spool = Spool::new(max);
+    if response.content_length().is_some_and(|len| len > max) {
+        spool.spill().map_err(http::Error::Read)?;
+    }
+    let size = http::copy_body(response, "image/", &mut spool)?;
+    spool.into_image(size).map_err(http::Error::Read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"{"num": 1110, "title": "Click and Drag", "safe_title": "Click and Drag",
+            "alt": null, "img": "https://imgs.xkcd.com/comics/click_and_drag.png",
+            "year": "2012", "month": "9", "day": "19",
+            "extra_parts": {"pre": "", "headerextra": "", "post": ""}}"#;
+        let comic: XkcdComic = serde_json::from_str(json).unwrap();
+        assert_eq!(comic.num, 1110);
+        assert_eq!(comic.alt, "");
+        assert_eq!(comic.link, "");
+        assert_eq!(comic.date(), NaiveDate::from_ymd_opt(2012, 9, 19));
+
+        assert!(serde_json::from_str::<XkcdComic>(r#"{"title": "No number"}"#).is_err());
+    }
+
+    #[test]
+    fn test_spool() {
+        let mut spool = Spool::new(4);
+        spool.write_all(b"abcd").unwrap();
+        let Image::Buffered(bytes) = spool.into_image(4).unwrap() else {
+            panic!("an image within the limit is spooled");
+        };
+        assert_eq!(bytes, b"abcd");
+
+        // Spills once the limit is passed, with what was buffered before
+        let gif = b"GIF89a\x02\x00\x03\x00";
+        let mut spool = Spool::new(4);
+        spool.write_all(&gif[..4]).unwrap();
+        spool.write_all(&gif[4..]).unwrap();
+        let Image::Spooled(image) = spool.into_image(gif.len() as u64).unwrap() else {
+            panic!("an image over the limit is buffered");
+        };
+        assert_eq!(image.size(), 10);
+        assert_eq!(image.read_at(0, 4096).unwrap(), gif);
+        assert_eq!(image.dimensions(), Some((2, 3)));
+        assert_eq!(image.sha256(), <[u8; 32]>::from(Sha256::digest(gif)));
+    }
+}
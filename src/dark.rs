@@ -0,0 +1,54 @@
+use image::{ImageFormat, Rgba};
+use std::io::Cursor;
+
+/// Background and ink levels of the dark variant: white paper becomes a
+/// dark grey rather than pure black, and black lines a soft light grey.
+const DARK_LEVEL: u16 = 0x1e;
+const LIGHT_LEVEL: u16 = 0xe0;
+
+/// Renders a dark-mode PNG of a comic by inverting it and compressing the
+/// result into `DARK_LEVEL..=LIGHT_LEVEL`. Returns `None` if the image
+/// cannot be decoded.
+pub fn dark_variant(image: &[u8]) -> Option<Vec<u8>> {
+    let mut pixels = image::load_from_memory(image).ok()?.to_rgba8();
+    for Rgba(pixel) in pixels.pixels_mut() {
+        for channel in &mut pixel[..3] {
+            *channel = adjust(*channel);
+        }
+    }
+
+    let mut png = Vec::new();
+    pixels
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
+
+fn adjust(value: u8) -> u8 {
+    let inverted = 255 - value as u16;
+    (DARK_LEVEL + inverted * (LIGHT_LEVEL - DARK_LEVEL) / 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_dark_variant() {
+        let mut original = RgbaImage::from_pixel(2, 1, Rgba([255, 255, 255, 255]));
+        original.put_pixel(1, 0, Rgba([0, 0, 0, 128]));
+        let mut png = Vec::new();
+        original
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let dark = image::load_from_memory(&dark_variant(&png).unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(dark.get_pixel(0, 0), &Rgba([0x1e, 0x1e, 0x1e, 255]));
+        assert_eq!(dark.get_pixel(1, 0), &Rgba([0xe0, 0xe0, 0xe0, 128]));
+
+        assert_eq!(dark_variant(b"not an image"), None);
+    }
+}
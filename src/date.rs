@@ -0,0 +1,70 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Locale, NaiveDate};
+use std::fmt::{self, Write};
+
+/// Format used for date.txt unless one is given on the command line.
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+/// Rejects strftime formats chrono cannot render for a date, either because
+/// they don't parse or because they ask for a time of day or time zone,
+/// such as `%H` or `%z`, which a date doesn't have.
+pub fn parse_format(format: &str) -> Result<String, String> {
+    let sample = NaiveDate::from_ymd_opt(2006, 1, 1).expect("valid date");
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+        || format_date(sample, format, Locale::POSIX).is_err()
+    {
+        return Err(format!("invalid strftime format: {}", format));
+    }
+    Ok(format.to_string())
+}
+
+/// Parses a locale such as `de_DE` or `fr_FR.UTF-8`.
+pub fn parse_locale(locale: &str) -> Result<Locale, String> {
+    let name = locale.split(['.', '@']).next().unwrap_or_default();
+    Locale::try_from(name).map_err(|_| format!("unknown locale: {}", locale))
+}
+
+/// The time locale of the environment (LC_ALL, LC_TIME, then LANG), falling
+/// back to POSIX.
+pub fn locale_from_env() -> Locale {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| parse_locale(&value).ok())
+        .unwrap_or(Locale::POSIX)
+}
+
+/// Renders `date`, failing instead of panicking like `to_string` would
+/// if the format needs more than a date.
+pub fn format_date(date: NaiveDate, format: &str, locale: Locale) -> Result<String, fmt::Error> {
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format_localized(format, locale))?;
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        let date = NaiveDate::from_ymd_opt(2007, 12, 5).unwrap();
+        assert_eq!(
+            format_date(date, DEFAULT_FORMAT, Locale::POSIX).unwrap(),
+            "2007-12-05"
+        );
+        assert_eq!(
+            format_date(date, "%A %-d %B %Y", parse_locale("de_DE.UTF-8").unwrap()).unwrap(),
+            "Mittwoch 5 Dezember 2007"
+        );
+        assert!(format_date(date, "%Y %H:%M", Locale::POSIX).is_err());
+
+        assert!(parse_format("%d.%m.%Y").is_ok());
+        assert!(parse_format("%Q").is_err());
+        assert!(parse_format("%Y %H:%M").is_err());
+        assert!(parse_format("%z").is_err());
+        assert!(parse_format("%s").is_err());
+        assert!(parse_locale("xx_YY").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+/// Reads the width and height of a PNG, GIF or JPEG image from its header
+/// without decoding the pixel data.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // The IHDR chunk always comes first: length, "IHDR", width, height
+        let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+        Some((width, height))
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        let width = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?);
+        let height = u16::from_le_bytes(data.get(8..10)?.try_into().ok()?);
+        Some((width.into(), height.into()))
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg_dimensions(data)
+    } else {
+        None
+    }
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        let length = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        // SOF0-SOF15 carry the frame size, except DHT (C4), JPG (C8) and DAC (CC)
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let height = u16::from_be_bytes(data.get(pos + 5..pos + 7)?.try_into().ok()?);
+            let width = u16::from_be_bytes(data.get(pos + 7..pos + 9)?.try_into().ok()?);
+            return Some((width.into(), height.into()));
+        }
+        pos += 2 + length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&740u32.to_be_bytes());
+        png.extend_from_slice(&320u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((740, 320)));
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&[0x2c, 0x01, 0x90, 0x01]);
+        assert_eq!(image_dimensions(&gif), Some((300, 400)));
+
+        // SOI, an APP0 segment, then SOF0 with height 200 and width 600
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x00,
+            0xc8, 0x02, 0x58,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((600, 200)));
+
+        assert_eq!(image_dimensions(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(image_dimensions(b"<html>"), None);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::comic;
+use crate::history::History;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+/// One finding: what was checked, and the fix if it failed.
+struct Check {
+    what: &'static str,
+    problem: Option<String>,
+    /// Failing only matters for some setups, so it is reported as a note.
+    advisory: bool,
+}
+
+/// Checks the things mounting depends on and prints a fix for every
+/// problem found. Returns whether everything looked fine.
+pub fn run() -> bool {
+    let checks = [
+        check_fusermount(),
+        check_dev_fuse(),
+        check_fuse_conf(),
+        check_network(),
+        check_state_dir(),
+    ];
+    let mut healthy = true;
+    for check in &checks {
+        match &check.problem {
+            None => println!("ok       {}", check.what),
+            Some(fix) if check.advisory => println!("note     {}\n         {}", check.what, fix),
+            Some(fix) => {
+                healthy = false;
+                println!("PROBLEM  {}\n         {}", check.what, fix);
+            }
+        }
+    }
+    healthy
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+fn check_fusermount() -> Check {
+    let found = ["fusermount3", "fusermount"]
+        .iter()
+        .any(|program| find_in_path(program).is_some());
+    Check {
+        what: "fusermount is installed",
+        advisory: false,
+        problem: (!found)
+            .then(|| "Install FUSE, e.g. `apt install fuse3` or `dnf install fuse3`.".to_string()),
+    }
+}
+
+fn check_dev_fuse() -> Check {
+    let path = CString::new("/dev/fuse").unwrap();
+    let accessible = unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } == 0;
+    let problem = if !Path::new("/dev/fuse").exists() {
+        Some("/dev/fuse is missing. Load the module with `modprobe fuse`.".to_string())
+    } else if !accessible {
+        Some(
+            "/dev/fuse isn't readable and writable by you. Check its permissions (usually 0666)."
+                .to_string(),
+        )
+    } else {
+        None
+    };
+    Check {
+        what: "/dev/fuse is usable",
+        advisory: false,
+        problem,
+    }
+}
+
+/// Whether a fuse.conf enables `user_allow_other`.
+fn allows_other(conf: &str) -> bool {
+    conf.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .any(|line| line == "user_allow_other")
+}
+
+fn check_fuse_conf() -> Check {
+    let conf = std::fs::read_to_string("/etc/fuse.conf").unwrap_or_default();
+    Check {
+        what: "user_allow_other is enabled in /etc/fuse.conf (only needed to share the mount)",
+        advisory: true,
+        problem: (!allows_other(&conf)).then(|| {
+            "Add a `user_allow_other` line to /etc/fuse.conf if other users should see the mount."
+                .to_string()
+        }),
+    }
+}
+
+fn check_network() -> Check {
+    Check {
+        what: "xkcd.com is reachable",
+        advisory: false,
+        problem: comic::fetch_latest_info().err().map(|e| {
+            format!(
+                "{}. Check your network and proxy settings, or --ca-bundle if TLS is intercepted.",
+                e
+            )
+        }),
+    }
+}
+
+fn check_state_dir() -> Check {
+    let problem = match History::default_path().as_deref().and_then(Path::parent) {
+        None => {
+            Some("Neither XDG_STATE_HOME nor HOME is set, so no history can be kept.".to_string())
+        }
+        Some(dir) => std::fs::create_dir_all(dir)
+            .and_then(|_| tempfile_in(dir))
+            .err()
+            .map(|e| {
+                format!(
+                    "{}: {}. Fix its permissions or pass --no-history.",
+                    dir.display(),
+                    e
+                )
+            }),
+    };
+    Check {
+        what: "the state directory is writable",
+        advisory: false,
+        problem,
+    }
+}
+
+fn tempfile_in(dir: &Path) -> std::io::Result<()> {
+    let path = dir.join(format!(".doctor-{}", std::process::id()));
+    std::fs::write(&path, b"")?;
+    std::fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_other() {
+        assert!(allows_other("# mount_max = 1000\nuser_allow_other\n"));
+        assert!(allows_other("  user_allow_other  # for xkcdfs\n"));
+        assert!(!allows_other("#user_allow_other\n"));
+        assert!(!allows_other(""));
+    }
+}
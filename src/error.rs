@@ -0,0 +1,55 @@
+use libc::{EACCES, EINVAL, EIO, ENODATA, ENOENT, ENOTTY, ERANGE, EROFS};
+use thiserror::Error;
+
+/// Why a filesystem request failed. `errno` is the one place that decides
+/// what the kernel is told.
+#[derive(Debug, Error)]
+pub enum XkcdFsError {
+    /// No such file, including files that only exist for some comics.
+    /// The only failure reported as ENOENT.
+    #[error("no such file")]
+    NotFound,
+    /// Reading an image spooled to disk failed.
+    #[error("reading spooled image: {0}")]
+    Io(#[source] std::io::Error),
+    #[error("invalid argument")]
+    InvalidArgument,
+    /// Anything that would modify the filesystem.
+    #[error("read-only filesystem")]
+    ReadOnly,
+    #[error("permission denied")]
+    AccessDenied,
+    #[error("no such attribute")]
+    NoAttribute,
+    /// The caller's xattr buffer can't hold the value.
+    #[error("buffer too small")]
+    BufferTooSmall,
+    #[error("unsupported ioctl")]
+    UnsupportedIoctl,
+}
+
+impl XkcdFsError {
+    pub fn errno(&self) -> i32 {
+        match self {
+            XkcdFsError::NotFound => ENOENT,
+            XkcdFsError::Io(e) => e.raw_os_error().unwrap_or(EIO),
+            XkcdFsError::InvalidArgument => EINVAL,
+            XkcdFsError::ReadOnly => EROFS,
+            XkcdFsError::AccessDenied => EACCES,
+            XkcdFsError::NoAttribute => ENODATA,
+            XkcdFsError::BufferTooSmall => ERANGE,
+            XkcdFsError::UnsupportedIoctl => ENOTTY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno() {
+        assert_eq!(XkcdFsError::NotFound.errno(), ENOENT);
+        assert_eq!(XkcdFsError::ReadOnly.to_string(), "read-only filesystem");
+    }
+}
@@ -0,0 +1,184 @@
+use fuser::{PollHandle, ReplyData};
+use libc::{EAGAIN, EINTR, EINVAL, O_NONBLOCK};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a read is parked before it is failed with EINTR. The kernel
+/// can't interrupt a read the filesystem has received, so this is how long
+/// a reader that was killed can hang; readers that are still alive retry.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Backing state for the `/.events` file.
+///
+/// Every new comic appends one line to the log. Each open handle only sees
+/// the lines published after it was opened, and reads past the end of the
+/// log are parked until the next line arrives instead of returning EOF, for
+/// up to `READ_TIMEOUT`. Non-blocking reads get EAGAIN instead, and poll()
+/// tells them when to try again.
+#[derive(Default)]
+pub struct EventLog {
+    log: Vec<u8>,
+    next_fh: u64,
+    handles: HashMap<u64, EventHandle>,
+    pending_reads: Vec<PendingRead>,
+    poll_handles: Vec<PollHandle>,
+}
+
+struct EventHandle {
+    /// Log length when the handle was opened; reads are relative to it.
+    base: usize,
+    /// Handle-relative offset just past the last byte returned to the reader.
+    position: usize,
+}
+
+struct PendingRead {
+    fh: u64,
+    offset: usize,
+    size: usize,
+    reply: ReplyData,
+    parked_at: Instant,
+}
+
+impl EventLog {
+    pub fn open(&mut self) -> u64 {
+        self.next_fh += 1;
+        let handle = EventHandle {
+            base: self.log.len(),
+            position: 0,
+        };
+        self.handles.insert(self.next_fh, handle);
+        self.next_fh
+    }
+
+    pub fn release(&mut self, fh: u64) {
+        self.handles.remove(&fh);
+        let (cancelled, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|read| read.fh == fh);
+        self.pending_reads = pending;
+        for read in cancelled {
+            read.reply.error(EINTR);
+        }
+    }
+
+    /// Answers the read immediately if there is unread data, otherwise keeps
+    /// the reply until `publish` is called or the read times out. `flags`
+    /// are the handle's open flags.
+    pub fn read(&mut self, fh: u64, offset: i64, size: u32, flags: i32, reply: ReplyData) {
+        if offset < 0 || !self.handles.contains_key(&fh) {
+            reply.error(EINVAL);
+            return;
+        }
+        let read = PendingRead {
+            fh,
+            offset: offset as usize,
+            size: size as usize,
+            reply,
+            parked_at: Instant::now(),
+        };
+        match self.try_complete(read) {
+            Some(read) if flags & O_NONBLOCK != 0 => read.reply.error(EAGAIN),
+            Some(read) => self.pending_reads.push(read),
+            None => {}
+        }
+    }
+
+    /// Fails the reads parked for `READ_TIMEOUT` or longer as of `now`.
+    fn expire(&mut self, now: Instant) {
+        let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|read| now.duration_since(read.parked_at) >= READ_TIMEOUT);
+        self.pending_reads = pending;
+        for read in expired {
+            read.reply.error(EINTR);
+        }
+    }
+
+    /// Returns POLLIN when the handle has unread events. Otherwise the poll
+    /// handle is kept so the kernel can be notified on the next publish.
+    pub fn poll(&mut self, fh: u64, ph: PollHandle, schedule_notify: bool) -> u32 {
+        if self.has_unread(fh) {
+            return libc::POLLIN as u32;
+        }
+        if schedule_notify {
+            self.poll_handles.push(ph);
+        }
+        0
+    }
+
+    pub fn publish(&mut self, line: &str) {
+        self.log.extend_from_slice(line.as_bytes());
+
+        for read in std::mem::take(&mut self.pending_reads) {
+            if let Some(read) = self.try_complete(read) {
+                self.pending_reads.push(read);
+            }
+        }
+        for ph in self.poll_handles.drain(..) {
+            let _ = ph.notify();
+        }
+    }
+
+    fn has_unread(&self, fh: u64) -> bool {
+        self.handles
+            .get(&fh)
+            .is_some_and(|handle| handle.base + handle.position < self.log.len())
+    }
+
+    fn unread_range(&self, fh: u64, offset: usize, size: usize) -> Option<(usize, usize)> {
+        let handle = self.handles.get(&fh)?;
+        let start = handle.base + offset;
+        if start >= self.log.len() {
+            return None;
+        }
+        Some((
+            start,
+            std::cmp::min(start.saturating_add(size), self.log.len()),
+        ))
+    }
+
+    fn try_complete(&mut self, read: PendingRead) -> Option<PendingRead> {
+        let Some((start, end)) = self.unread_range(read.fh, read.offset, read.size) else {
+            return Some(read);
+        };
+        if let Some(handle) = self.handles.get_mut(&read.fh) {
+            handle.position = read.offset + (end - start);
+        }
+        read.reply.data(&self.log[start..end]);
+        None
+    }
+}
+
+/// Times out parked reads in the background, so no reader waits much longer
+/// than `READ_TIMEOUT` for the next comic.
+pub fn expire_parked_reads(events: Arc<Mutex<EventLog>>) {
+    thread::spawn(move || loop {
+        thread::sleep(READ_TIMEOUT / 2);
+        events.lock().unwrap().expire(Instant::now());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handles_only_see_later_events() {
+        let mut events = EventLog::default();
+        events.publish("1\tBarrel - Part 1\n");
+
+        let fh = events.open();
+        assert_eq!(events.unread_range(fh, 0, 100), None);
+        assert!(!events.has_unread(fh));
+
+        events.publish("2\tPetit Trees (sketch)\n");
+        assert!(events.has_unread(fh));
+        let (start, end) = events.unread_range(fh, 0, 100).unwrap();
+        assert_eq!(&events.log[start..end], b"2\tPetit Trees (sketch)\n");
+
+        // Reads past what has been published have nothing to return yet
+        assert_eq!(events.unread_range(fh, end - start, 100), None);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::http;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const EXPLAIN_API_URL: &str = "https://www.explainxkcd.com/wiki/api.php";
+
+#[derive(Deserialize)]
+struct Response {
+    query: Query,
+}
+
+#[derive(Deserialize)]
+struct Query {
+    pages: HashMap<String, Page>,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    #[serde(default)]
+    categories: Vec<Category>,
+}
+
+#[derive(Deserialize)]
+struct Category {
+    title: String,
+}
+
+/// Fetches the categories explainxkcd files comic `num` under, such as
+/// "Programming" or "Comics featuring Cueball". Hidden maintenance
+/// categories are left out.
+pub fn fetch_tags(num: u32) -> Result<Vec<String>, http::Error> {
+    // Pages named after the bare number redirect to "353: Python" and so on
+    let url = format!(
+        "{}?action=query&prop=categories&clshow=!hidden&cllimit=max&redirects=1&format=json&titles={}",
+        EXPLAIN_API_URL, num
+    );
+    let response: Response = http::read_json(http::client().get(url).send()?)?;
+    Ok(tags(response.query.pages.into_values()))
+}
+
+fn tags(pages: impl Iterator<Item = Page>) -> Vec<String> {
+    let mut tags: Vec<String> = pages
+        .flat_map(|page| page.categories)
+        .map(|category| match category.title.strip_prefix("Category:") {
+            Some(tag) => tag.to_string(),
+            None => category.title,
+        })
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        let category = |title: &str| Category {
+            title: title.to_string(),
+        };
+        let pages = vec![
+            Page {
+                categories: vec![
+                    category("Category:Programming"),
+                    category("Category:Python"),
+                ],
+            },
+            Page { categories: vec![] },
+            Page {
+                categories: vec![category("Category:Programming"), category("Physics")],
+            },
+        ];
+        assert_eq!(
+            tags(pages.into_iter()),
+            ["Physics", "Programming", "Python"]
+        );
+    }
+}
@@ -1,9 +1,36 @@
+use crate::archive::ArchiveEntry;
+use crate::clock::Clock;
+use crate::comic::{SpooledImage, XkcdComic};
+use crate::dark::dark_variant;
+use crate::date;
+use crate::dimensions::image_dimensions;
+use crate::error::XkcdFsError;
+use crate::events::EventLog;
+use crate::history::History;
+use crate::http;
+use crate::idle::Activity;
+use crate::ioctl::{self, Stats};
+use crate::poller::Update;
+use crate::range::ComicRange;
+use crate::sanitize;
+use crate::slideshow;
+use crate::template::{self, TemplateVars};
+use crate::text;
+use chrono::{Locale, NaiveDate};
+use fuser::consts::{FOPEN_DIRECT_IO, FUSE_POLL_SCHEDULE_NOTIFY};
 use fuser::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+    fuse_forget_one, FileAttr, FileType, Filesystem, Notifier, PollHandle, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyOpen, ReplyPoll, ReplyXattr, Request,
 };
-use libc::{EINVAL, ENOENT};
+use libc::{R_OK, W_OK, X_OK};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
-use std::time::{Duration, UNIX_EPOCH};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const TTL: Duration = Duration::from_secs(1);
 
@@ -18,8 +45,8 @@ const DIR_ATTR: FileAttr = FileAttr {
     kind: FileType::Directory,
     perm: 0o555,
     nlink: 2,
-    uid: 1000,
-    gid: 1000,
+    uid: 0,
+    gid: 0,
     rdev: 0,
     flags: 0,
     blksize: 512,
@@ -29,7 +56,7 @@ const XKCD_DESKTOP_CONTENT: &str = "[Desktop Entry]\nType=Link\nName=XKCD\nURL=h
 const XKCD_DESKTOP_ATTR: FileAttr = FileAttr {
     ino: 2,
     size: XKCD_DESKTOP_CONTENT.len() as u64,
-    blocks: 1,
+    blocks: blocks(XKCD_DESKTOP_CONTENT.len() as u64),
     atime: UNIX_EPOCH,
     mtime: UNIX_EPOCH,
     ctime: UNIX_EPOCH,
@@ -37,8 +64,8 @@ const XKCD_DESKTOP_ATTR: FileAttr = FileAttr {
     kind: FileType::RegularFile,
     perm: 0o444,
     nlink: 1,
-    uid: 1000,
-    gid: 1000,
+    uid: 0,
+    gid: 0,
     rdev: 0,
     flags: 0,
     blksize: 512,
@@ -50,7 +77,7 @@ xkcd is by Randall Munroe.\n";
 const ABOUT_ATTR: FileAttr = FileAttr {
     ino: 3,
     size: ABOUT_CONTENT.len() as u64,
-    blocks: 1,
+    blocks: blocks(ABOUT_CONTENT.len() as u64),
     atime: UNIX_EPOCH,
     mtime: UNIX_EPOCH,
     ctime: UNIX_EPOCH,
@@ -58,13 +85,35 @@ const ABOUT_ATTR: FileAttr = FileAttr {
     kind: FileType::RegularFile,
     perm: 0o444,
     nlink: 1,
-    uid: 1000,
-    gid: 1000,
+    uid: 0,
+    gid: 0,
     rdev: 0,
     flags: 0,
     blksize: 512,
 };
 
+/// Number of 512-byte blocks `st_blocks` reports for a file of `size`
+/// bytes, regardless of `blksize`, so that `du` adds up the real sizes.
+const fn blocks(size: u64) -> u64 {
+    size.div_ceil(512)
+}
+
+/// Looking up a name that doesn't exist replies with inode 0, which the
+/// kernel caches as a negative entry for the TTL, like ENOENT.
+const NEGATIVE_ENTRY_ATTR: FileAttr = FileAttr {
+    ino: 0,
+    size: 0,
+    blocks: 0,
+    ..ABOUT_ATTR
+};
+
+const EVENTS_INO: u64 = 13;
+
+/// Inodes whose attributes or contents change when a new comic is published.
+const LATEST_DEPENDENT_INOS: [u64; 24] = [
+    1, 100, 4, 5, 6, 7, 8, 9, 10, 11, 12, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+];
+
 const SUBDIR_ATTR: FileAttr = FileAttr {
     ino: 100,
     size: 0,
@@ -76,8 +125,8 @@ const SUBDIR_ATTR: FileAttr = FileAttr {
     kind: FileType::Directory,
     perm: 0o555,
     nlink: 2,
-    uid: 1000,
-    gid: 1000,
+    uid: 0,
+    gid: 0,
     rdev: 0,
     flags: 0,
     blksize: 512,
@@ -85,16 +134,145 @@ const SUBDIR_ATTR: FileAttr = FileAttr {
 
 pub struct XkcdFs {
     pub latest_title: String,
+    pub latest_safe_title: String,
     pub latest_alt: String,
     pub latest_img: Vec<u8>,
+    /// A latest image too large to keep in memory, in which case
+    /// `latest_img` is empty and reads go to the spooled copy.
+    pub latest_img_spooled: Option<SpooledImage>,
+    /// Dark-mode rendering of `latest_img`, generated on first access.
+    pub latest_dark: OnceCell<Option<Vec<u8>>>,
+    /// Hex SHA-256 of the latest image, computed on first access.
+    pub latest_sha256: OnceCell<String>,
+    pub latest_num: u32,
+    pub latest_date: Option<NaiveDate>,
+    pub latest_img_url: String,
+    pub latest_link: String,
+    pub latest_news: String,
+    /// explainxkcd categories of the latest comic, for tags.txt.
+    pub latest_tags: Vec<String>,
+    pub archive: Vec<ArchiveEntry>,
+    /// When the latest comic was picked up, used as the directories' mtime.
+    pub updated: SystemTime,
+    /// The most recent comics, newest first, for slideshow.html.
+    pub recent: Vec<XkcdComic>,
+    pub events: Arc<Mutex<EventLog>>,
+    pub updates: Receiver<Update>,
+    /// Asks the poller to check for a new comic now.
+    pub refresh: Sender<()>,
+    /// When the latest comic was last known to be current.
+    pub revalidated: Instant,
+    /// Whether the last check failed, so the latest comic may be outdated.
+    pub stale: bool,
+    pub history: History,
+    pub activity: Activity,
+    /// Handles currently open per inode, files and directories alike.
+    pub open_handles: BTreeMap<u64, u32>,
+    /// Lookups the kernel still holds per inode, counted down by forget.
+    pub lookups: BTreeMap<u64, u64>,
+    pub clock: Box<dyn Clock>,
+    pub options: Options,
+}
+
+/// User-selectable presentation settings.
+#[derive(Default)]
+pub struct Options {
+    /// Also expose Windows `.url` and macOS `.webloc` links per comic.
+    pub interop_links: bool,
+    /// Wrap alt.txt at this many columns.
+    pub alt_wrap: Option<usize>,
+    /// Template for the names of per-comic files, see `template::render`.
+    pub file_template: Option<String>,
+    /// Resolve names regardless of case, as Samba and macOS clients expect.
+    pub case_insensitive: bool,
+    /// Avoid characters and names Windows cannot represent.
+    pub windows_names: bool,
+    /// strftime format for date.txt, `date::DEFAULT_FORMAT` if unset.
+    pub date_format: Option<String>,
+    /// Locale for month and day names in date.txt, POSIX if unset.
+    pub date_locale: Option<Locale>,
+    /// Permission bits cleared from files and from directories, like the
+    /// fmask and dmask options of the FAT driver.
+    pub file_mask: u16,
+    pub dir_mask: u16,
+    /// Owner reported for every file and directory.
+    pub uid: u32,
+    pub gid: u32,
+    /// Comics to list in index.txt, archive.csv and slideshow.html.
+    pub comics: ComicRange,
+    /// How old the latest comic may get before the next access asks the
+    /// poller to check for a new one, in addition to its regular polling.
+    pub max_stale: Option<Duration>,
+    /// Number of comics in slideshow.html, which is left out if unset.
+    pub slideshow: Option<usize>,
 }
 
 impl XkcdFs {
+    /// Picks up any new comics found by the poller since the last request,
+    /// and whether its checks work, and notes the request for --idle-timeout.
+    /// Once the latest comic is older than `max_stale`, a check is started in
+    /// the background and the current data is served in the meantime.
+    fn apply_updates(&mut self) {
+        self.activity.touch();
+        if self
+            .options
+            .max_stale
+            .is_some_and(|max_stale| self.clock.now().duration_since(self.revalidated) >= max_stale)
+        {
+            // The poller only stops once the filesystem is gone
+            let _ = self.refresh.send(());
+            self.revalidated = self.clock.now();
+        }
+        while let Ok(update) = self.updates.try_recv() {
+            let comic = match update {
+                Update::New(comic) => *comic,
+                Update::Checked { failed } => {
+                    self.stale = failed;
+                    continue;
+                }
+            };
+            self.stale = false;
+            if let Some(mut archive) = comic.archive {
+                archive.retain(|entry| self.options.comics.contains(entry.num));
+                self.archive = archive;
+            }
+            let info = comic.info;
+            let listed = self.options.comics.contains(info.num);
+            if listed && !self.archive.iter().any(|entry| entry.num == info.num) {
+                self.archive.push(ArchiveEntry {
+                    num: info.num,
+                    year: info.year.parse().unwrap_or(0),
+                    month: info.month.parse().unwrap_or(0),
+                    day: info.day.parse().unwrap_or(0),
+                    title: info.title.clone(),
+                });
+            }
+            if let Some(count) = self.options.slideshow.filter(|_| listed) {
+                self.recent.insert(0, info.clone());
+                self.recent.truncate(count);
+            }
+            self.updated = self.clock.system_now();
+            self.revalidated = self.clock.now();
+            self.latest_date = info.date();
+            self.latest_title = info.title;
+            self.latest_safe_title = info.safe_title;
+            self.latest_alt = info.alt;
+            (self.latest_img, self.latest_img_spooled) = comic.image.into_parts();
+            self.latest_dark = OnceCell::new();
+            self.latest_sha256 = OnceCell::new();
+            self.latest_num = info.num;
+            self.latest_img_url = info.img;
+            self.latest_link = info.link;
+            self.latest_news = info.news;
+            self.latest_tags = comic.tags;
+        }
+    }
+
     fn create_file_attr(&self, ino: u64, size: u64) -> FileAttr {
         FileAttr {
             ino,
             size,
-            blocks: 1,
+            blocks: blocks(size),
             atime: UNIX_EPOCH,
             mtime: UNIX_EPOCH,
             ctime: UNIX_EPOCH,
@@ -102,95 +280,831 @@ impl XkcdFs {
             kind: FileType::RegularFile,
             perm: 0o444,
             nlink: 1,
-            uid: 1000,
-            gid: 1000,
+            uid: 0,
+            gid: 0,
             rdev: 0,
             flags: 0,
             blksize: 512,
         }
     }
 
-    fn get_file_attr(&self, ino: u64) -> Result<FileAttr, i32> {
+    /// Children of a directory, not including `.` and `..`.
+    fn dir_entries(&self, ino: u64) -> Option<Vec<(u64, FileType, String)>> {
+        match ino {
+            1 => {
+                let mut entries = vec![
+                    (2, FileType::RegularFile, "xkcd.desktop"),
+                    (3, FileType::RegularFile, "about.txt"),
+                    (10, FileType::RegularFile, "index.txt"),
+                    (11, FileType::RegularFile, "archive.csv"),
+                    (12, FileType::RegularFile, "latest.txt"),
+                    (22, FileType::RegularFile, "stats.json"),
+                ];
+                if self.options.slideshow.is_some() {
+                    entries.push((20, FileType::RegularFile, "slideshow.html"));
+                }
+                entries.push((EVENTS_INO, FileType::RegularFile, ".events"));
+                // Nothing to show until the first comic has been fetched
+                if self.latest_num != 0 {
+                    entries.push((100, FileType::Directory, "latest"));
+                    entries.push((24, FileType::Symlink, "latest-image"));
+                    // For status bar widgets that only `cat` fixed paths
+                    entries.push((25, FileType::RegularFile, "latest-title.txt"));
+                    entries.push((26, FileType::RegularFile, "latest-alt.txt"));
+                }
+                Some(
+                    entries
+                        .into_iter()
+                        .map(|(ino, kind, name)| (ino, kind, name.to_string()))
+                        .collect(),
+                )
+            }
+            100 => Some(self.latest_entries()),
+            _ => None,
+        }
+    }
+
+    /// What readdir returns after `offset`, as (cookie, ino, kind, name).
+    ///
+    /// Cookies come from inode numbers rather than positions, and entries
+    /// are listed in cookie order, so a listing that is interrupted while a
+    /// new comic adds or removes entries resumes where it left off without
+    /// skipping or repeating any of the others.
+    fn dir_listing(&self, ino: u64, offset: i64) -> Option<Vec<(i64, u64, FileType, String)>> {
+        let mut entries = vec![
+            (1, ino, FileType::Directory, ".".to_string()),
+            (2, 1, FileType::Directory, "..".to_string()),
+        ];
+        entries.extend(
+            self.dir_entries(ino)?
+                .into_iter()
+                .map(|(child, kind, name)| (child as i64 + 2, child, kind, name)),
+        );
+        entries.sort_by_key(|entry| entry.0);
+        entries.retain(|entry| entry.0 > offset);
+        Some(entries)
+    }
+
+    /// Resolves `name` inside directory `parent`, ignoring case if the
+    /// mount asked for it. An exact match always wins.
+    fn find_entry(&self, parent: u64, name: &str) -> Option<u64> {
+        let entries = self.dir_entries(parent)?;
+        let exact = entries.iter().find(|entry| entry.2 == name);
+        let found = match exact {
+            None if self.options.case_insensitive => {
+                let name = name.to_lowercase();
+                entries.iter().find(|entry| entry.2.to_lowercase() == name)
+            }
+            found => found,
+        };
+        found.map(|entry| entry.0)
+    }
+
+    /// Files in the latest comic directory, named after the file template.
+    fn latest_entries(&self) -> Vec<(u64, FileType, String)> {
+        let mut files = vec![
+            (4, "title.txt"),
+            (5, "alt.txt"),
+            (6, "image.png"),
+            (7, "num.txt"),
+        ];
+        if !self.latest_link.is_empty() {
+            files.push((8, "link.txt"));
+        }
+        files.push((9, "news.txt"));
+        if self.latest_date.is_some() {
+            files.push((19, "date.txt"));
+        }
+        if !self.latest_tags.is_empty() {
+            files.push((21, "tags.txt"));
+        }
+        files.push((14, "comic.desktop"));
+        // Only for images that decode, judged by the header alone so that
+        // listing never renders the dark variant. Spooled images are too
+        // large to render.
+        if image_dimensions(&self.latest_img).is_some() {
+            files.push((18, "image-dark.png"));
+        }
+        if self.latest_img_dimensions().is_some() {
+            files.push((17, "dimensions.txt"));
+        }
+        files.push((23, "sha256sum.txt"));
+        if self.options.interop_links {
+            files.push((15, "comic.url"));
+            files.push((16, "comic.webloc"));
+        }
+
+        let mut names: Vec<String> = files
+            .iter()
+            .map(|(_, name)| self.file_name(name))
+            .map(|name| {
+                if self.options.windows_names {
+                    sanitize::windows_name(name)
+                } else {
+                    name
+                }
+            })
+            .collect();
+        sanitize::disambiguate(&mut names);
+        files
+            .into_iter()
+            .zip(names)
+            .map(|((ino, _), name)| (ino, FileType::RegularFile, name))
+            .collect()
+    }
+
+    fn file_name(&self, name: &str) -> String {
+        match &self.options.file_template {
+            Some(file_template) => sanitize::file_name(template::render(
+                file_template,
+                &TemplateVars {
+                    num: self.latest_num,
+                    title: &self.latest_title,
+                    safe_title: &self.latest_safe_title,
+                    name,
+                },
+            )),
+            None => name.to_string(),
+        }
+    }
+
+    /// Where a symlink points. `latest-image` follows the image's current
+    /// name, so scripts can keep using one path whatever the file template.
+    fn symlink_target(&self, ino: u64) -> Option<String> {
         match ino {
+            24 if self.latest_num != 0 => self
+                .latest_entries()
+                .into_iter()
+                .find(|entry| entry.0 == 6)
+                .map(|entry| format!("latest/{}", entry.2)),
+            _ => None,
+        }
+    }
+
+    fn latest_img_sha256(&self) -> &str {
+        self.latest_sha256.get_or_init(|| {
+            let digest = match &self.latest_img_spooled {
+                Some(image) => image.sha256(),
+                None => Sha256::digest(&self.latest_img).into(),
+            };
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        })
+    }
+
+    /// A line `sha256sum -c` checks the image against, run from a copy of
+    /// the directory.
+    fn sha256sum_content(&self) -> String {
+        let name = self
+            .latest_entries()
+            .into_iter()
+            .find(|entry| entry.0 == 6)
+            .map_or_else(|| "image.png".to_string(), |entry| entry.2);
+        format!("{}  {}\n", self.latest_img_sha256(), name)
+    }
+
+    fn latest_img_dimensions(&self) -> Option<(u32, u32)> {
+        match &self.latest_img_spooled {
+            Some(image) => image.dimensions(),
+            None => image_dimensions(&self.latest_img),
+        }
+    }
+
+    fn latest_dark(&self) -> Option<&[u8]> {
+        self.latest_dark
+            .get_or_init(|| dark_variant(&self.latest_img))
+            .as_deref()
+    }
+
+    fn index_content(&self) -> String {
+        self.archive
+            .iter()
+            .map(|entry| format!("{}\t{}\n", entry.num, entry.title))
+            .collect()
+    }
+
+    fn latest_txt_content(&self) -> String {
+        format!("{}\n{}\n", self.latest_num, self.latest_title)
+    }
+
+    fn date_txt_content(&self) -> Option<String> {
+        let format = self.options.date_format.as_deref();
+        let locale = self.options.date_locale.unwrap_or(Locale::POSIX);
+        Some(format!(
+            "{}\n",
+            date::format_date(
+                self.latest_date?,
+                format.unwrap_or(date::DEFAULT_FORMAT),
+                locale
+            )
+            .ok()?
+        ))
+    }
+
+    fn comic_desktop_content(&self) -> String {
+        format!(
+            "[Desktop Entry]\nType=Link\nName={}\nURL=https://xkcd.com/{}/\n",
+            self.latest_title, self.latest_num
+        )
+    }
+
+    fn comic_url_content(&self) -> String {
+        format!(
+            "[InternetShortcut]\r\nURL=https://xkcd.com/{}/\r\n",
+            self.latest_num
+        )
+    }
+
+    fn comic_webloc_content(&self) -> String {
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ",
+                "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+                "<plist version=\"1.0\">\n<dict>\n\t<key>URL</key>\n",
+                "\t<string>https://xkcd.com/{}/</string>\n</dict>\n</plist>\n"
+            ),
+            self.latest_num
+        )
+    }
+
+    fn count_open(&mut self, ino: u64) {
+        *self.open_handles.entry(ino).or_insert(0) += 1;
+    }
+
+    fn count_release(&mut self, ino: u64) {
+        if let Some(count) = self.open_handles.get_mut(&ino) {
+            *count -= 1;
+            if *count == 0 {
+                self.open_handles.remove(&ino);
+            }
+        }
+    }
+
+    /// Drops the generated files held in memory. They are only generated
+    /// again when opened, not when latest/ is listed.
+    fn drop_cache(&mut self) {
+        self.latest_dark = OnceCell::new();
+    }
+
+    fn count_lookup(&mut self, ino: u64) {
+        *self.lookups.entry(ino).or_insert(0) += 1;
+    }
+
+    /// Once the kernel has forgotten every lookup of an inode, whatever was
+    /// cached for it is dropped until it is looked up again. Only the dark
+    /// image is cached per inode; everything else is the latest comic itself.
+    fn count_forget(&mut self, ino: u64, nlookup: u64) {
+        let Some(count) = self.lookups.get_mut(&ino) else {
+            return;
+        };
+        *count = count.saturating_sub(nlookup);
+        if *count == 0 {
+            self.lookups.remove(&ino);
+            if ino == 18 {
+                self.latest_dark = OnceCell::new();
+            }
+        }
+    }
+
+    /// Aggregate numbers about the listed comics. `cached_bytes` counts the
+    /// images currently held in memory, `open_handles` the handles open per
+    /// inode, which only grow if releases never arrive, `backoff_seconds`
+    /// how much longer xkcd.com asked us to stop sending requests, and `stale`
+    /// whether the last check failed so the latest comic may be outdated.
+    fn stats_json_content(&self) -> String {
+        let mut per_year = BTreeMap::new();
+        for entry in &self.archive {
+            *per_year.entry(entry.year).or_insert(0) += 1;
+        }
+        let per_year: Vec<String> = per_year
+            .iter()
+            .map(|(year, count)| format!("\"{}\": {}", year, count))
+            .collect();
+        let date = |entry: Option<&ArchiveEntry>| match entry {
+            Some(entry) => format!("\"{:04}-{:02}-{:02}\"", entry.year, entry.month, entry.day),
+            None => "null".to_string(),
+        };
+        let cached_bytes = self.latest_img.len()
+            + self
+                .latest_dark
+                .get()
+                .and_then(Option::as_ref)
+                .map_or(0, Vec::len);
+        let open_handles: Vec<String> = self
+            .open_handles
+            .iter()
+            .map(|(ino, count)| format!("\"{}\": {}", ino, count))
+            .collect();
+        format!(
+            concat!(
+                "{{\n  \"comics\": {},\n  \"latest\": {},\n",
+                "  \"first_date\": {},\n  \"latest_date\": {},\n",
+                "  \"per_year\": {{{}}},\n  \"cached_bytes\": {},\n",
+                "  \"open_handles\": {{{}}},\n  \"backoff_seconds\": {},\n",
+                "  \"stale\": {}\n}}\n"
+            ),
+            self.archive.len(),
+            self.latest_num,
+            date(self.archive.first()),
+            date(self.archive.last()),
+            per_year.join(", "),
+            cached_bytes,
+            open_handles.join(", "),
+            http::backoff_remaining(self.clock.now()).map_or(0, |wait| wait.as_secs()),
+            self.stale
+        )
+    }
+
+    /// The archive page only has one title per comic and no image URLs, so
+    /// older rows repeat the title as the safe title and leave `img` empty.
+    /// Only the latest comic's row is filled in from its metadata.
+    fn archive_csv_content(&self) -> String {
+        let mut csv = String::from("num,date,safe_title,title,img\n");
+        for entry in &self.archive {
+            let (safe_title, title, img) = if entry.num == self.latest_num {
+                (
+                    self.latest_safe_title.as_str(),
+                    self.latest_title.as_str(),
+                    self.latest_img_url.as_str(),
+                )
+            } else {
+                (entry.title.as_str(), entry.title.as_str(), "")
+            };
+            csv.push_str(&format!(
+                "{},{:04}-{:02}-{:02},{},{},{}\n",
+                entry.num,
+                entry.year,
+                entry.month,
+                entry.day,
+                csv_field(safe_title),
+                csv_field(title),
+                csv_field(img)
+            ));
+        }
+        csv
+    }
+
+    /// A directory's link count: its entry in the parent, its own `.` and
+    /// the `..` of each subdirectory.
+    fn dir_nlink(&self, ino: u64) -> u32 {
+        let subdirs = self.dir_entries(ino).map_or(0, |entries| {
+            entries
+                .iter()
+                .filter(|entry| entry.1 == FileType::Directory)
+                .count()
+        });
+        2 + subdirs as u32
+    }
+
+    /// Attributes of an inode, owned by the configured user and group and
+    /// with the configured permission masks applied.
+    fn get_file_attr(&self, ino: u64) -> Result<FileAttr, XkcdFsError> {
+        let attr = match ino {
             1 => Ok(DIR_ATTR),
             2 => Ok(XKCD_DESKTOP_ATTR),
             3 => Ok(ABOUT_ATTR),
-            4 => Ok(self.create_file_attr(4, self.latest_title.len() as u64)),
-            5 => Ok(self.create_file_attr(5, self.latest_alt.len() as u64)),
-            6 => Ok(self.create_file_attr(6, self.latest_img.len() as u64)),
+            4..=12 | 14..=23 | 25..=26 => Ok(self.create_file_attr(ino, self.file_len(ino)?)),
+            EVENTS_INO => Ok(self.create_file_attr(EVENTS_INO, 0)),
+            24 => match self.symlink_target(ino) {
+                Some(target) => Ok(FileAttr {
+                    kind: FileType::Symlink,
+                    perm: 0o777,
+                    ..self.create_file_attr(ino, target.len() as u64)
+                }),
+                None => Err(XkcdFsError::NotFound),
+            },
             100 => Ok(SUBDIR_ATTR),
-            _ => Err(ENOENT),
+            _ => Err(XkcdFsError::NotFound),
+        }?;
+        let (mask, nlink) = match attr.kind {
+            FileType::Directory => (self.options.dir_mask, self.dir_nlink(ino)),
+            _ => (self.options.file_mask, attr.nlink),
+        };
+        // Directory listings change with every new comic
+        let (mtime, ctime) = match attr.kind {
+            FileType::Directory => (self.updated, self.updated),
+            _ => (attr.mtime, attr.ctime),
+        };
+        Ok(FileAttr {
+            mtime,
+            ctime,
+            perm: attr.perm & !mask,
+            nlink,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            ..attr
+        })
+    }
+
+    fn file_len(&self, ino: u64) -> Result<u64, XkcdFsError> {
+        match (ino, &self.latest_img_spooled) {
+            (6, Some(image)) => Ok(image.size()),
+            (18, _) => self.dark_len(),
+            _ => Ok(self.file_data(ino)?.len() as u64),
+        }
+    }
+
+    /// The size of image-dark.png without rendering it: 0 until it has been
+    /// opened, which is why it is opened for direct I/O.
+    fn dark_len(&self) -> Result<u64, XkcdFsError> {
+        if image_dimensions(&self.latest_img).is_none() {
+            return Err(XkcdFsError::NotFound);
+        }
+        match self.latest_dark.get() {
+            Some(Some(dark)) => Ok(dark.len() as u64),
+            Some(None) => Err(XkcdFsError::NotFound),
+            None => Ok(0),
         }
     }
 
-    fn read_data(&self, ino: u64, offset: i64, size: u32) -> Result<&[u8], i32> {
-        let data = match ino {
-            2 => XKCD_DESKTOP_CONTENT.as_bytes(),
-            3 => ABOUT_CONTENT.as_bytes(),
-            4 => self.latest_title.as_bytes(),
-            5 => self.latest_alt.as_bytes(),
-            6 => &self.latest_img,
-            _ => return Err(ENOENT),
+    fn file_data(&self, ino: u64) -> Result<Cow<'_, [u8]>, XkcdFsError> {
+        let data: Cow<'_, [u8]> = match ino {
+            2 => XKCD_DESKTOP_CONTENT.as_bytes().into(),
+            3 => ABOUT_CONTENT.as_bytes().into(),
+            4 => self.latest_title.as_bytes().into(),
+            5 => match self.options.alt_wrap {
+                Some(width) => text::wrap(&self.latest_alt, width).into_bytes().into(),
+                None => self.latest_alt.as_bytes().into(),
+            },
+            6 => self.latest_img.as_slice().into(),
+            7 => self.latest_num.to_string().into_bytes().into(),
+            8 if !self.latest_link.is_empty() => self.latest_link.as_bytes().into(),
+            9 => self.latest_news.as_bytes().into(),
+            10 => self.index_content().into_bytes().into(),
+            11 => self.archive_csv_content().into_bytes().into(),
+            12 => self.latest_txt_content().into_bytes().into(),
+            14 => self.comic_desktop_content().into_bytes().into(),
+            15 if self.options.interop_links => self.comic_url_content().into_bytes().into(),
+            16 if self.options.interop_links => self.comic_webloc_content().into_bytes().into(),
+            17 => match self.latest_img_dimensions() {
+                Some((width, height)) => format!("{}x{}\n", width, height).into_bytes().into(),
+                // Only listed for images that decode
+                None => return Err(XkcdFsError::NotFound),
+            },
+            18 => match self.latest_dark() {
+                Some(dark) => dark.into(),
+                // Listed by the header alone, so it may turn out not to decode
+                None => return Err(XkcdFsError::NotFound),
+            },
+            19 => match self.date_txt_content() {
+                Some(date) => date.into_bytes().into(),
+                None => return Err(XkcdFsError::NotFound),
+            },
+            20 if self.options.slideshow.is_some() => {
+                slideshow::render(&self.recent).into_bytes().into()
+            }
+            22 => self.stats_json_content().into_bytes().into(),
+            23 => self.sha256sum_content().into_bytes().into(),
+            25 if self.latest_num != 0 => return self.file_data(4),
+            26 if self.latest_num != 0 => return self.file_data(5),
+            21 if !self.latest_tags.is_empty() => {
+                let mut tags = self.latest_tags.join("\n");
+                tags.push('\n');
+                tags.into_bytes().into()
+            }
+            _ => return Err(XkcdFsError::NotFound),
         };
+        Ok(data)
+    }
+
+    /// Extended attributes of an inode, as (name, value) pairs.
+    fn xattrs(&self, ino: u64) -> Vec<(&'static str, String)> {
+        match ino {
+            6 => {
+                // The same provenance attributes browsers set on downloads
+                let mut xattrs = vec![
+                    ("user.xdg.origin.url", self.latest_img_url.clone()),
+                    (
+                        "user.xdg.referrer.url",
+                        format!("https://xkcd.com/{}/", self.latest_num),
+                    ),
+                ];
+                if let Some((width, height)) = self.latest_img_dimensions() {
+                    xattrs.push(("user.xkcd.width", width.to_string()));
+                    xattrs.push(("user.xkcd.height", height.to_string()));
+                }
+                xattrs.push(("user.xkcd.sha256", self.latest_img_sha256().to_string()));
+                xattrs
+            }
+            _ => Vec::new(),
+        }
+    }
 
+    fn read_data(&self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, XkcdFsError> {
         if offset < 0 {
-            return Err(EINVAL);
+            return Err(XkcdFsError::InvalidArgument);
         }
+        if let (6, Some(image)) = (ino, &self.latest_img_spooled) {
+            return image
+                .read_at(offset as u64, size as u64)
+                .map_err(XkcdFsError::Io);
+        }
+
+        let data = self.file_data(ino)?;
         if offset as u64 >= data.len() as u64 {
-            return Ok(b"");
+            return Ok(Vec::new());
         }
 
         let offset = offset as usize;
         let size = size as usize;
         let end = std::cmp::min(offset.saturating_add(size), data.len());
-        Ok(&data[offset..end])
+        Ok(data[offset..end].to_vec())
+    }
+}
+
+/// Tells the kernel to drop what it has cached about the previous latest
+/// comic, so open file manager windows refresh without user interaction.
+pub fn invalidate_latest(notifier: &Notifier) {
+    // Errors only mean the kernel never looked the inode up, so nothing is stale
+    for ino in LATEST_DEPENDENT_INOS {
+        let _ = notifier.inval_inode(ino, 0, 0);
+    }
+    // These come and go depending on the comic
+    let _ = notifier.inval_entry(1, OsStr::new("latest"));
+    let _ = notifier.inval_entry(1, OsStr::new("latest-image"));
+    let _ = notifier.inval_entry(100, OsStr::new("link.txt"));
+    let _ = notifier.inval_entry(100, OsStr::new("tags.txt"));
+    let _ = notifier.inval_entry(100, OsStr::new("sha256sum.txt"));
+}
+
+/// Replies with the xattr value or name list, or just its length when the
+/// caller passes a size of 0 to probe for the buffer size it needs.
+fn reply_xattr(data: &[u8], size: u32, reply: ReplyXattr) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if (size as usize) < data.len() {
+        reply.error(XkcdFsError::BufferTooSmall.errno());
+    } else {
+        reply.data(data);
+    }
+}
+
+/// Checks an access(2) `mask` for the user `uid` in group `gid` against the
+/// reported mode, the way the kernel would for a local file. Only the
+/// primary group is known, so supplementary groups are not considered.
+fn check_access(attr: &FileAttr, uid: u32, gid: u32, mask: i32) -> Result<(), XkcdFsError> {
+    if mask & W_OK != 0 {
+        return Err(XkcdFsError::ReadOnly);
+    }
+    let perm = i32::from(attr.perm);
+    let granted = if uid == 0 {
+        // root may read anything, but only execute what someone can execute
+        R_OK | if perm & 0o111 != 0 { X_OK } else { 0 }
+    } else if uid == attr.uid {
+        (perm >> 6) & 0o7
+    } else if gid == attr.gid {
+        (perm >> 3) & 0o7
+    } else {
+        perm & 0o7
+    };
+    if mask & (R_OK | X_OK) & !granted != 0 {
+        return Err(XkcdFsError::AccessDenied);
+    }
+    Ok(())
+}
+
+/// Parses an octal permission mask such as `022` or `0077`.
+pub fn parse_mask(mask: &str) -> Result<u16, String> {
+    match u16::from_str_radix(mask, 8) {
+        Ok(mask) if mask <= 0o7777 => Ok(mask),
+        _ => Err(format!("{} is not an octal permission mask", mask)),
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
     }
 }
 
 impl Filesystem for XkcdFs {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let name = name.to_str();
-        let ino = match (parent, name) {
-            (1, Some("latest")) => 100,
-            (1, Some("xkcd.desktop")) => 2,
-            (1, Some("about.txt")) => 3,
-            (100, Some("title.txt")) => 4,
-            (100, Some("alt.txt")) => 5,
-            (100, Some("image.png")) => 6,
-            _ => {
-                reply.error(ENOENT);
+        self.apply_updates();
+        let ino = match name.to_str().and_then(|name| self.find_entry(parent, name)) {
+            Some(ino) => ino,
+            None if self.dir_entries(parent).is_some() => {
+                // Lets the kernel remember the miss instead of asking again
+                reply.entry(&TTL, &NEGATIVE_ENTRY_ATTR, 0);
+                return;
+            }
+            None => {
+                reply.error(XkcdFsError::NotFound.errno());
                 return;
             }
         };
 
         match self.get_file_attr(ino) {
-            Ok(attr) => reply.entry(&TTL, &attr, 0),
-            Err(e) => reply.error(e),
+            Ok(attr) => {
+                self.count_lookup(ino);
+                reply.entry(&TTL, &attr, 0);
+            }
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.count_forget(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuse_forget_one]) {
+        for node in nodes {
+            self.count_forget(node.nodeid, node.nlookup);
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        self.apply_updates();
+        match self.symlink_target(ino) {
+            Some(target) => reply.data(target.as_bytes()),
+            None => reply.error(XkcdFsError::InvalidArgument.errno()),
         }
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        self.apply_updates();
         match self.get_file_attr(ino) {
             Ok(attr) => reply.attr(&TTL, &attr),
-            Err(e) => reply.error(e),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        self.apply_updates();
+        match self
+            .get_file_attr(ino)
+            .and_then(|attr| check_access(&attr, req.uid(), req.gid(), mask))
+        {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        self.apply_updates();
+        self.count_open(ino);
+        if ino == EVENTS_INO {
+            // Direct I/O so every read reaches us even though the size is 0
+            let fh = self.events.lock().unwrap().open();
+            reply.opened(fh, FOPEN_DIRECT_IO);
+            return;
+        }
+        if ino == 6 {
+            // Opening the image is what counts as viewing the comic. Failing to
+            // save the history must not stop anyone from reading it.
+            let _ = self
+                .history
+                .record(self.latest_num, self.clock.system_now());
         }
+        if ino == 18 {
+            // Rendered on open; direct I/O because its size may have been
+            // reported as 0 before
+            self.latest_dark();
+            reply.opened(0, FOPEN_DIRECT_IO);
+            return;
+        }
+        reply.opened(0, 0);
     }
 
     fn read(
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         size: u32,
-        _flags: i32,
+        flags: i32,
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
+        self.apply_updates();
+        if ino == EVENTS_INO {
+            self.events
+                .lock()
+                .unwrap()
+                .read(fh, offset, size, flags, reply);
+            return;
+        }
         match self.read_data(ino, offset, size) {
-            Ok(data) => reply.data(data),
-            Err(e) => reply.error(e),
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.count_release(ino);
+        if ino == EVENTS_INO {
+            self.events.lock().unwrap().release(fh);
+        }
+        reply.ok();
+    }
+
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        self.count_open(ino);
+        reply.opened(0, 0);
+    }
+
+    fn releasedir(&mut self, _req: &Request, ino: u64, _fh: u64, _flags: i32, reply: ReplyEmpty) {
+        self.count_release(ino);
+        reply.ok();
+    }
+
+    fn poll(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        _events: u32,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        if ino == EVENTS_INO {
+            let schedule_notify = flags & FUSE_POLL_SCHEDULE_NOTIFY != 0;
+            let revents = self.events.lock().unwrap().poll(fh, ph, schedule_notify);
+            reply.poll(revents);
+            return;
+        }
+        // Everything else is static data that can always be read
+        reply.poll((libc::POLLIN | libc::POLLRDNORM) as u32);
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        self.apply_updates();
+        if ino != 1 {
+            reply.error(XkcdFsError::UnsupportedIoctl.errno());
+            return;
+        }
+        match cmd {
+            ioctl::REFRESH => {
+                // The poller only stops once the filesystem is gone
+                let _ = self.refresh.send(());
+                reply.ioctl(0, &[]);
+            }
+            ioctl::DROP_CACHE => {
+                self.drop_cache();
+                reply.ioctl(0, &[]);
+            }
+            ioctl::STATS => {
+                let stats = Stats {
+                    latest_num: self.latest_num,
+                    comics: self.archive.len() as u32,
+                };
+                reply.ioctl(0, &stats.to_bytes());
+            }
+            _ => reply.error(XkcdFsError::UnsupportedIoctl.errno()),
+        }
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        self.apply_updates();
+        if let Err(e) = self.get_file_attr(ino) {
+            reply.error(e.errno());
+            return;
+        }
+        match self
+            .xattrs(ino)
+            .into_iter()
+            .find(|(n, _)| OsStr::new(n) == name)
+        {
+            Some((_, value)) => reply_xattr(value.as_bytes(), size, reply),
+            None => reply.error(XkcdFsError::NoAttribute.errno()),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        self.apply_updates();
+        if let Err(e) = self.get_file_attr(ino) {
+            reply.error(e.errno());
+            return;
         }
+        let mut names = Vec::new();
+        for (name, _) in self.xattrs(ino) {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        reply_xattr(&names, size, reply);
     }
 
     fn readdir(
@@ -201,29 +1115,13 @@ impl Filesystem for XkcdFs {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        let entries = if ino == 1 {
-            vec![
-                (1, FileType::Directory, "."),
-                (1, FileType::Directory, ".."),
-                (2, FileType::RegularFile, "xkcd.desktop"),
-                (3, FileType::RegularFile, "about.txt"),
-                (100, FileType::Directory, "latest"),
-            ]
-        } else if ino == 100 {
-            vec![
-                (100, FileType::Directory, "."),
-                (1, FileType::Directory, ".."),
-                (4, FileType::RegularFile, "title.txt"),
-                (5, FileType::RegularFile, "alt.txt"),
-                (6, FileType::RegularFile, "image.png"),
-            ]
-        } else {
-            reply.error(ENOENT);
+        self.apply_updates();
+        let Some(entries) = self.dir_listing(ino, offset) else {
+            reply.error(XkcdFsError::NotFound.errno());
             return;
         };
-
-        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+        for (cookie, child, kind, name) in entries {
+            if reply.add(child, cookie, kind, name) {
                 break;
             }
         }
@@ -233,31 +1131,589 @@ impl Filesystem for XkcdFs {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::mpsc;
     use std::vec;
 
     use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::comic::{Comic, Image};
+    use libc::{EACCES, EINVAL, ENOENT, EROFS};
+
+    fn test_fs() -> XkcdFs {
+        XkcdFs {
+            latest_title: "Python".to_string(),
+            latest_safe_title: "Python".to_string(),
+            latest_alt: "Alt".to_string(),
+            latest_img: vec![1, 2, 3],
+            latest_img_spooled: None,
+            latest_dark: OnceCell::new(),
+            latest_sha256: OnceCell::new(),
+            latest_num: 353,
+            latest_date: None,
+            latest_img_url: String::new(),
+            latest_link: String::new(),
+            latest_news: String::new(),
+            latest_tags: Vec::new(),
+            archive: Vec::new(),
+            updated: UNIX_EPOCH,
+            recent: Vec::new(),
+            events: Default::default(),
+            updates: mpsc::channel().1,
+            refresh: mpsc::channel().0,
+            revalidated: Instant::now(),
+            stale: false,
+            history: History::default(),
+            activity: Activity::default(),
+            open_handles: BTreeMap::new(),
+            lookups: BTreeMap::new(),
+            clock: Box::new(SystemClock),
+            options: Options::default(),
+        }
+    }
 
     #[test]
     fn test_get_file_attr() {
         let fs = XkcdFs {
             latest_title: "Test Title".to_string(),
+            latest_safe_title: "Test Title".to_string(),
             latest_alt: "Test Alt".to_string(),
             latest_img: vec![1, 2, 3, 4],
+            latest_news: "News".to_string(),
+            options: Options {
+                file_mask: 0o027,
+                dir_mask: 0o022,
+                uid: 1000,
+                gid: 100,
+                ..Default::default()
+            },
+            ..test_fs()
         };
 
         assert_eq!(fs.get_file_attr(1).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.get_file_attr(1).unwrap().uid, 1000);
+        assert_eq!(fs.get_file_attr(1).unwrap().nlink, 3); // 2 + the latest directory
+        assert_eq!(fs.get_file_attr(100).unwrap().nlink, 2);
+        assert_eq!(fs.get_file_attr(4).unwrap().nlink, 1);
+        assert_eq!(fs.get_file_attr(3).unwrap().gid, 100);
+        assert_eq!(fs.get_file_attr(1).unwrap().perm, 0o555);
+        assert_eq!(fs.get_file_attr(3).unwrap().perm, 0o440);
         assert_eq!(fs.get_file_attr(2).unwrap().kind, FileType::RegularFile);
         assert_eq!(fs.get_file_attr(4).unwrap().size, 10); // "Test Title"
         assert_eq!(fs.get_file_attr(6).unwrap().size, 4);
-        assert_eq!(fs.get_file_attr(999).unwrap_err(), ENOENT);
+        assert_eq!(fs.get_file_attr(6).unwrap().blocks, 1);
+        assert_eq!(fs.get_file_attr(7).unwrap().size, 3); // "353"
+        assert_eq!(fs.get_file_attr(8).unwrap_err().errno(), ENOENT); // empty link
+        assert_eq!(fs.get_file_attr(9).unwrap().size, 4);
+        assert_eq!(fs.get_file_attr(12).unwrap().size, 15); // "353\nTest Title\n"
+        assert_eq!(fs.get_file_attr(15).unwrap_err().errno(), ENOENT); // interop links disabled
+        assert_eq!(fs.get_file_attr(18).unwrap_err().errno(), ENOENT); // image can't be decoded
+        assert_eq!(fs.get_file_attr(999).unwrap_err().errno(), ENOENT);
     }
 
     #[test]
-    fn test_read_data() {
+    fn test_blocks() {
+        assert_eq!(blocks(0), 0);
+        assert_eq!(blocks(1), 1);
+        assert_eq!(blocks(512), 1);
+        assert_eq!(blocks(513), 2);
+        assert_eq!(ABOUT_ATTR.blocks, 1);
+    }
+
+    #[test]
+    fn test_check_access() {
+        let file = FileAttr {
+            perm: 0o440,
+            uid: 1000,
+            gid: 100,
+            ..ABOUT_ATTR
+        };
+        assert_eq!(
+            check_access(&file, 1000, 1000, libc::F_OK).map_err(|e| e.errno()),
+            Ok(())
+        );
+        assert_eq!(
+            check_access(&file, 1000, 1000, R_OK).map_err(|e| e.errno()),
+            Ok(())
+        );
+        assert_eq!(
+            check_access(&file, 1001, 100, R_OK).map_err(|e| e.errno()),
+            Ok(())
+        );
+        assert_eq!(
+            check_access(&file, 1001, 1001, R_OK).map_err(|e| e.errno()),
+            Err(EACCES)
+        );
+        assert_eq!(
+            check_access(&file, 1000, 100, X_OK).map_err(|e| e.errno()),
+            Err(EACCES)
+        );
+        assert_eq!(
+            check_access(&file, 0, 0, R_OK).map_err(|e| e.errno()),
+            Ok(())
+        );
+        assert_eq!(
+            check_access(&file, 0, 0, X_OK).map_err(|e| e.errno()),
+            Err(EACCES)
+        );
+        assert_eq!(
+            check_access(&file, 1000, 100, W_OK).map_err(|e| e.errno()),
+            Err(EROFS)
+        );
+        assert_eq!(
+            check_access(&DIR_ATTR, 1001, 1001, R_OK | X_OK).map_err(|e| e.errno()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_parse_mask() {
+        assert_eq!(parse_mask("022"), Ok(0o022));
+        assert_eq!(parse_mask("0077"), Ok(0o077));
+        assert!(parse_mask("8").is_err());
+        assert!(parse_mask("17777").is_err());
+        assert!(parse_mask("").is_err());
+    }
+
+    #[test]
+    fn test_apply_updates() {
+        let (updates_tx, updates_rx) = mpsc::channel();
+        let (refresh_tx, refresh_rx) = mpsc::channel();
+        let clock = ManualClock::new();
+        let mut fs = XkcdFs {
+            updates: updates_rx,
+            refresh: refresh_tx,
+            revalidated: clock.now(),
+            clock: Box::new(clock.clone()),
+            options: Options {
+                max_stale: Some(Duration::from_secs(3600)),
+                ..Default::default()
+            },
+            ..test_fs()
+        };
+
+        // Fresh data is served without asking the poller
+        fs.apply_updates();
+        assert!(refresh_rx.try_recv().is_err());
+        clock.advance(Duration::from_secs(3599));
+        fs.apply_updates();
+        assert!(refresh_rx.try_recv().is_err());
+        clock.advance(Duration::from_secs(1));
+        fs.apply_updates();
+        assert!(refresh_rx.try_recv().is_ok());
+        // The check that was just started counts as revalidating
+        fs.apply_updates();
+        assert!(refresh_rx.try_recv().is_err());
+
+        updates_tx.send(Update::Checked { failed: true }).unwrap();
+        fs.apply_updates();
+        assert!(fs.stale);
+        assert_eq!(fs.latest_num, 353);
+        assert!(fs.latest_img_sha256().starts_with("039058c6"));
+
+        updates_tx
+            .send(Update::New(Box::new(Comic {
+                info: XkcdComic {
+                    num: 354,
+                    title: "Reverse Euphemisms".to_string(),
+                    safe_title: "Reverse Euphemisms".to_string(),
+                    alt: "Alt".to_string(),
+                    img: String::new(),
+                    link: String::new(),
+                    news: String::new(),
+                    year: "2007".to_string(),
+                    month: "12".to_string(),
+                    day: "7".to_string(),
+                },
+                image: Image::Buffered(vec![4, 5, 6]),
+                tags: vec!["Language".to_string()],
+                archive: Some(vec![ArchiveEntry {
+                    num: 353,
+                    year: 2007,
+                    month: 12,
+                    day: 5,
+                    title: "Python".to_string(),
+                }]),
+            })))
+            .unwrap();
+        fs.apply_updates();
+
+        assert_eq!(fs.latest_num, 354);
+        assert!(!fs.stale);
+        assert_eq!(fs.latest_date, NaiveDate::from_ymd_opt(2007, 12, 7));
+        assert_eq!(fs.latest_tags, ["Language"]);
+        assert_eq!(fs.archive.len(), 2);
+        // The cached hash went with the old image
+        assert!(fs.latest_img_sha256().starts_with("787c798e"));
+        assert!(fs.get_file_attr(1).unwrap().mtime > UNIX_EPOCH);
+        assert!(fs.get_file_attr(100).unwrap().ctime > UNIX_EPOCH);
+        assert_eq!(fs.get_file_attr(4).unwrap().mtime, UNIX_EPOCH);
+
+        // Images over --max-image-size are read from their spooled copy
+        fs.latest_img_spooled = Some(SpooledImage::from_bytes(b"spooled image"));
+        assert_eq!(fs.get_file_attr(6).unwrap().size, 13);
+        assert_eq!(fs.read_data(6, 8, 4096).unwrap(), b"image");
+        assert_eq!(fs.read_data(6, 13, 4096).unwrap(), Vec::<u8>::new());
+        assert_eq!(fs.read_data(6, 1 << 20, 4096).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_xattrs() {
         let fs = XkcdFs {
+            latest_img_url: "https://imgs.xkcd.com/comics/python.png".to_string(),
+            ..test_fs()
+        };
+
+        assert_eq!(
+            fs.xattrs(6),
+            vec![
+                (
+                    "user.xdg.origin.url",
+                    "https://imgs.xkcd.com/comics/python.png".to_string()
+                ),
+                ("user.xdg.referrer.url", "https://xkcd.com/353/".to_string()),
+                (
+                    "user.xkcd.sha256",
+                    "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81".to_string()
+                ),
+            ]
+        );
+        assert!(fs.xattrs(4).is_empty());
+    }
+
+    #[test]
+    fn test_latest_entries() {
+        let mut fs = test_fs();
+
+        let names =
+            |fs: &XkcdFs| -> Vec<String> { fs.latest_entries().into_iter().map(|e| e.2).collect() };
+        assert_eq!(names(&fs)[..3], ["title.txt", "alt.txt", "image.png"]);
+        assert!(!names(&fs).contains(&"link.txt".to_string()));
+
+        fs.options.file_template = Some("{num:04}-{safe_title}-{name}".to_string());
+        assert_eq!(names(&fs)[2], "0353-Python-image.png");
+        assert_eq!(fs.find_entry(100, "0353-Python-image.png"), Some(6));
+
+        fs.latest_safe_title = "Why?".to_string();
+        fs.options.windows_names = true;
+        assert_eq!(names(&fs)[2], "0353-Why_-image.png");
+        assert_eq!(
+            fs.sha256sum_content(),
+            "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81  0353-Why_-image.png\n"
+        );
+
+        // Spooled images are hashed as they download, and keep their size
+        // but not image-dark.png
+        fs.latest_sha256 = OnceCell::new();
+        fs.latest_img_spooled = Some(SpooledImage::from_bytes(b"GIF89a\x02\x00\x03\x00"));
+        assert!(names(&fs).contains(&"0353-Why_-sha256sum.txt".to_string()));
+        assert!(!names(&fs).iter().any(|name| name.contains("image-dark")));
+        assert_eq!(fs.file_data(17).unwrap(), b"2x3\n".as_slice());
+        assert_eq!(
+            fs.sha256sum_content(),
+            "a39ba6aebc26432b0b646b0ca5b569fa7c114063f8afaf052744f1554d28cca8  0353-Why_-image.png\n"
+        );
+    }
+
+    #[test]
+    fn test_find_entry() {
+        let mut fs = test_fs();
+
+        assert_eq!(fs.find_entry(1, "latest"), Some(100));
+        assert_eq!(fs.find_entry(100, "title.txt"), Some(4));
+        assert_eq!(fs.find_entry(100, "link.txt"), None);
+        assert_eq!(fs.find_entry(100, "Title.TXT"), None);
+        assert_eq!(fs.find_entry(4, "title.txt"), None);
+
+        fs.options.case_insensitive = true;
+        assert_eq!(fs.find_entry(100, "Title.TXT"), Some(4));
+        assert_eq!(fs.find_entry(1, "LATEST"), Some(100));
+
+        // Mounted without a startup fetch
+        fs.latest_num = 0;
+        assert_eq!(fs.find_entry(1, "latest"), None);
+    }
+
+    /// A 1x1 PNG, small enough to decode in every test.
+    fn png() -> Vec<u8> {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_dark_image_rendered_on_open() {
+        let fs = XkcdFs {
+            latest_img: png(),
+            ..test_fs()
+        };
+
+        // Listing and stat go by the header and leave the rendering to open
+        assert_eq!(fs.find_entry(100, "image-dark.png"), Some(18));
+        assert_eq!(fs.get_file_attr(18).unwrap().size, 0);
+        fs.get_file_attr(100).unwrap();
+        assert!(fs.latest_dark.get().is_none());
+
+        let dark = fs.read_data(18, 0, 1 << 20).unwrap();
+        assert!(dark.starts_with(b"\x89PNG"));
+        assert_eq!(fs.get_file_attr(18).unwrap().size, dark.len() as u64);
+
+        // An image whose header reads fine but whose data doesn't decode
+        // fails like dimensions.txt does for an unknown format
+        let fs = XkcdFs {
+            latest_img: png()[..33].to_vec(),
+            ..test_fs()
+        };
+        assert_eq!(fs.find_entry(100, "image-dark.png"), Some(18));
+        assert_eq!(
+            fs.read_data(18, 0, 4096).map_err(|e| e.errno()),
+            Err(ENOENT)
+        );
+        assert_eq!(fs.get_file_attr(18).map_err(|e| e.errno()), Err(ENOENT));
+    }
+
+    #[test]
+    fn test_drop_cache() {
+        let mut fs = XkcdFs {
+            latest_img: png(),
+            ..test_fs()
+        };
+        fs.read_data(18, 0, 1 << 20).unwrap();
+        assert!(fs.latest_dark.get().is_some());
+
+        fs.drop_cache();
+        fs.get_file_attr(100).unwrap();
+        fs.get_file_attr(18).unwrap();
+        assert!(fs.latest_dark.get().is_none());
+    }
+
+    #[test]
+    fn test_latest_image_symlink() {
+        let mut fs = test_fs();
+
+        assert_eq!(fs.find_entry(1, "latest-image"), Some(24));
+        assert_eq!(fs.get_file_attr(24).unwrap().kind, FileType::Symlink);
+        assert_eq!(fs.symlink_target(24).as_deref(), Some("latest/image.png"));
+        fs.options.file_template = Some("{num}-{name}".to_string());
+        assert_eq!(
+            fs.symlink_target(24).as_deref(),
+            Some("latest/353-image.png")
+        );
+        assert_eq!(fs.symlink_target(6), None);
+
+        fs.latest_num = 0;
+        assert_eq!(fs.find_entry(1, "latest-image"), None);
+    }
+
+    #[test]
+    fn test_count_forget() {
+        let mut fs = XkcdFs {
+            latest_img: png(),
+            latest_dark: OnceCell::from(Some(vec![4, 5, 6])),
+            ..test_fs()
+        };
+
+        // The dark image outlives forgets until the last lookup is gone
+        fs.count_lookup(18);
+        fs.count_lookup(18);
+        fs.count_forget(18, 1);
+        assert!(fs.latest_dark.get().is_some());
+        fs.count_forget(18, 1);
+        assert!(fs.latest_dark.get().is_none());
+        assert!(fs.lookups.is_empty());
+
+        // Listing latest/ and resolving names in it doesn't render it again
+        fs.get_file_attr(100).unwrap();
+        assert_eq!(fs.find_entry(100, "image-dark.png"), Some(18));
+        fs.get_file_attr(18).unwrap();
+        assert_eq!(fs.symlink_target(24).as_deref(), Some("latest/image.png"));
+        assert!(fs.latest_dark.get().is_none());
+
+        // Inodes that were never looked up are ignored
+        fs.count_forget(4, 1);
+        assert!(fs.lookups.is_empty());
+    }
+
+    #[test]
+    fn test_dir_listing() {
+        let mut fs = XkcdFs {
+            latest_num: 0,
+            ..test_fs()
+        };
+        let names = |listing: Vec<(i64, u64, FileType, String)>| {
+            listing.into_iter().map(|entry| entry.3).collect::<Vec<_>>()
+        };
+
+        let first = fs.dir_listing(1, 0).unwrap();
+        assert_eq!(
+            first[..2].iter().map(|e| &e.3).collect::<Vec<_>>(),
+            [".", ".."]
+        );
+        let (cookie, _, _, ref name) = first[4];
+        assert_eq!(name, "index.txt");
+        assert!(first.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        // The first comic arrives between two readdir calls
+        fs.latest_num = 353;
+        let rest = names(fs.dir_listing(1, cookie).unwrap());
+        assert_eq!(
+            rest,
+            [
+                "archive.csv",
+                "latest.txt",
+                ".events",
+                "stats.json",
+                "latest-image",
+                "latest-title.txt",
+                "latest-alt.txt",
+                "latest"
+            ]
+        );
+        assert!(fs.dir_listing(1, i64::MAX).unwrap().is_empty());
+        assert!(fs.dir_listing(4, 0).is_none());
+    }
+
+    #[test]
+    fn test_tree_snapshot() {
+        let fs = XkcdFs {
+            latest_alt: "I wrote 20 short programs in Python yesterday.".to_string(),
+            latest_date: NaiveDate::from_ymd_opt(2007, 12, 5),
+            latest_img_url: "https://imgs.xkcd.com/comics/python.png".to_string(),
+            latest_tags: vec!["Programming".to_string()],
+            archive: vec![
+                ArchiveEntry {
+                    num: 352,
+                    year: 2007,
+                    month: 12,
+                    day: 3,
+                    title: "Zealous Autoconfig".to_string(),
+                },
+                ArchiveEntry {
+                    num: 353,
+                    year: 2007,
+                    month: 12,
+                    day: 5,
+                    title: "Python".to_string(),
+                },
+            ],
+            options: Options {
+                interop_links: true,
+                slideshow: Some(2),
+                file_mask: 0o022,
+                ..Default::default()
+            },
+            ..test_fs()
+        };
+
+        // Every path with its kind, permissions and size, depth first
+        fn walk(fs: &XkcdFs, ino: u64, path: &str, out: &mut String) {
+            let attr = match fs.get_file_attr(ino) {
+                Ok(attr) => format!("{:?} {:o} {}", attr.kind, attr.perm, attr.size),
+                Err(e) => format!("error {}", e.errno()),
+            };
+            out.push_str(&format!("{} {}\n", path, attr));
+            for (child, _, name) in fs.dir_entries(ino).unwrap_or_default() {
+                walk(
+                    fs,
+                    child,
+                    &format!("{}/{}", path.trim_end_matches('/'), name),
+                    out,
+                );
+            }
+        }
+        let mut tree = String::new();
+        walk(&fs, 1, "/", &mut tree);
+        insta::assert_snapshot!("tree", tree);
+    }
+
+    #[test]
+    fn test_odd_requests() {
+        let mut fs = XkcdFs {
+            latest_date: NaiveDate::from_ymd_opt(2007, 12, 5),
+            latest_link: "https://example.com".to_string(),
+            latest_tags: vec!["Programming".to_string()],
+            options: Options {
+                interop_links: true,
+                case_insensitive: true,
+                slideshow: Some(3),
+                file_template: Some("{num:020}-{title}-{name}".to_string()),
+                ..Default::default()
+            },
+            ..test_fs()
+        };
+        let inos = (0..=130).chain([EVENTS_INO, u64::MAX / 2, u64::MAX]);
+        let offsets = [i64::MIN, -1, 0, 1, 2, 3, 4, i64::MAX];
+        let sizes = [0, 1, 4096, u32::MAX];
+        let names = ["", ".", "..", "/", "\0", "latest/title.txt", "LATEST", "ǅ"];
+
+        // Nothing may panic, whatever the kernel or a confused client sends
+        for latest_num in [353, 0, u32::MAX] {
+            fs.latest_num = latest_num;
+            for ino in inos.clone() {
+                let _ = fs.get_file_attr(ino);
+                let _ = fs.xattrs(ino);
+                for offset in offsets {
+                    for size in sizes {
+                        if let Ok(data) = fs.read_data(ino, offset, size) {
+                            assert!(data.len() <= size as usize);
+                        }
+                    }
+                }
+                for name in names {
+                    if let Some(child) = fs.find_entry(ino, name) {
+                        assert!(fs.get_file_attr(child).is_ok());
+                    }
+                }
+            }
+        }
+        assert_eq!(fs.read_data(4, -1, 10).unwrap_err().errno(), EINVAL);
+    }
+
+    #[test]
+    fn test_read_data() {
+        let mut fs = XkcdFs {
             latest_title: "Title".to_string(),
-            latest_alt: "Alt".to_string(),
+            latest_safe_title: "Safe Title".to_string(),
             latest_img: vec![10, 20, 30],
+            latest_num: 1,
+            latest_date: NaiveDate::from_ymd_opt(2006, 1, 1),
+            latest_img_url: "https://imgs.xkcd.com/comics/barrel_cropped_(1).jpg".to_string(),
+            latest_link: "https://example.com/".to_string(),
+            latest_tags: vec!["Comics from 2006".to_string(), "Romance".to_string()],
+            archive: vec![
+                ArchiveEntry {
+                    num: 1,
+                    year: 2006,
+                    month: 1,
+                    day: 1,
+                    title: "Barrel - Part 1".to_string(),
+                },
+                ArchiveEntry {
+                    num: 2,
+                    year: 2006,
+                    month: 1,
+                    day: 1,
+                    title: "Petit, \"Trees\"".to_string(),
+                },
+            ],
+            options: Options {
+                interop_links: true,
+                alt_wrap: Some(2),
+                file_template: None,
+                case_insensitive: false,
+                windows_names: false,
+                date_format: Some("%-d %B %Y".to_string()),
+                date_locale: Some(Locale::fr_FR),
+                comics: ComicRange::default(),
+                max_stale: None,
+                file_mask: 0,
+                dir_mask: 0,
+                uid: 0,
+                gid: 0,
+                slideshow: None,
+            },
+            ..test_fs()
         };
 
         // Test reading title (ino 4)
@@ -276,12 +1732,70 @@ mod tests {
         let data = fs.read_data(4, 100, 10).unwrap();
         assert_eq!(data, b"");
 
+        // Test generated and optional files
+        let data = fs.read_data(7, 0, 100).unwrap();
+        assert_eq!(data, b"1");
+        let data = fs.read_data(8, 0, 100).unwrap();
+        assert_eq!(data, b"https://example.com/");
+
+        let data = fs.read_data(14, 0, 100).unwrap();
+        assert_eq!(
+            data,
+            b"[Desktop Entry]\nType=Link\nName=Title\nURL=https://xkcd.com/1/\n"
+        );
+
+        let data = fs.read_data(15, 0, 100).unwrap();
+        assert_eq!(data, b"[InternetShortcut]\r\nURL=https://xkcd.com/1/\r\n");
+
+        let data = fs.read_data(10, 0, 100).unwrap();
+        assert_eq!(data, b"1\tBarrel - Part 1\n2\tPetit, \"Trees\"\n");
+
+        let data = fs.read_data(11, 0, 1000).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "num,date,safe_title,title,img\n\
+             1,2006-01-01,Safe Title,Title,https://imgs.xkcd.com/comics/barrel_cropped_(1).jpg\n\
+             2,2006-01-01,\"Petit, \"\"Trees\"\"\",\"Petit, \"\"Trees\"\"\",\n"
+        );
+
+        let data = fs.read_data(19, 0, 100).unwrap();
+        assert_eq!(data, "1 janvier 2006\n".as_bytes());
+
+        fs.count_open(6);
+        fs.count_open(6);
+        fs.count_open(100);
+        fs.count_release(100);
+        fs.count_release(6);
+        let data = fs.read_data(22, 0, 1000).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "{\n  \"comics\": 2,\n  \"latest\": 1,\n  \"first_date\": \"2006-01-01\",\n  \
+             \"latest_date\": \"2006-01-01\",\n  \"per_year\": {\"2006\": 2},\n  \"cached_bytes\": 3,\n  \
+             \"open_handles\": {\"6\": 1},\n  \"backoff_seconds\": 0,\n  \"stale\": false\n}\n"
+        );
+
+        let data = fs.read_data(21, 0, 100).unwrap();
+        assert_eq!(data, b"Comics from 2006\nRomance\n");
+
+        assert_eq!(fs.read_data(25, 0, 100).unwrap(), b"Title");
+        assert_eq!(fs.read_data(26, 0, 100).unwrap(), b"Alt");
+
+        let data = fs.read_data(23, 0, 100).unwrap();
+        assert_eq!(
+            data,
+            b"6951bbd9c2178b2a9d01714687307afda1eb6161ff8c398486a76683e7b42925  image.png\n"
+        );
+
+        // Test wrapped alt text
+        let data = fs.read_data(5, 0, 100).unwrap();
+        assert_eq!(data, b"Alt");
+
         // Test negative offset
         let err = fs.read_data(4, -1, 10).unwrap_err();
-        assert_eq!(err, EINVAL);
+        assert_eq!(err.errno(), EINVAL);
 
         // Test unknown inode
         let err = fs.read_data(999, 0, 10).unwrap_err();
-        assert_eq!(err, ENOENT);
+        assert_eq!(err.errno(), ENOENT);
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When each comic was last viewed, persisted as `NUM<TAB>UNIX_SECONDS`
+/// lines so it survives remounts.
+#[derive(Default)]
+pub struct History {
+    /// Where the history is saved; `None` keeps it in memory only.
+    path: Option<PathBuf>,
+    viewed: BTreeMap<u32, u64>,
+}
+
+impl History {
+    /// `$XDG_STATE_HOME/xkcdfs/history.tsv`, or `~/.local/state/...` when
+    /// XDG_STATE_HOME is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })?;
+        Some(state_home.join("xkcdfs").join("history.tsv"))
+    }
+
+    /// Loads the history at `path`, skipping lines that don't parse. A
+    /// missing file is an empty history.
+    pub fn load(path: PathBuf) -> History {
+        let viewed = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (num, when) = line.split_once('\t')?;
+                Some((num.parse().ok()?, when.parse().ok()?))
+            })
+            .collect();
+        History {
+            path: Some(path),
+            viewed,
+        }
+    }
+
+    /// Records that comic `num` was viewed at `when` and saves the history.
+    pub fn record(&mut self, num: u32, when: SystemTime) -> io::Result<()> {
+        let secs = when
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.viewed.insert(num, secs);
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .viewed
+            .iter()
+            .map(|(num, when)| format!("{}\t{}\n", num, when))
+            .collect();
+        // Write to a temporary file first so a crash never truncates the history
+        let tmp = path.with_extension("tsv.tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_history_round_trip() {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-history-{}", std::process::id()));
+        let path = dir.join("history.tsv");
+
+        let mut history = History::load(path.clone());
+        assert!(history.viewed.is_empty());
+        history
+            .record(353, UNIX_EPOCH + Duration::from_secs(1_196_812_800))
+            .unwrap();
+        history
+            .record(1, UNIX_EPOCH + Duration::from_secs(60))
+            .unwrap();
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+
+        let history = History::load(path);
+        assert_eq!(
+            history.viewed.into_iter().collect::<Vec<_>>(),
+            [(1, 60), (353, 1_196_812_800)]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use crate::comic::XkcdComic;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// `$XDG_CONFIG_HOME/xkcdfs/hooks/new-comic.d`, or `~/.config/...` when
+/// XDG_CONFIG_HOME is not set.
+pub fn new_comic_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("xkcdfs/hooks/new-comic.d"))
+}
+
+/// The comic's metadata as `XKCD_*` environment variables for hooks.
+pub fn environment(comic: &XkcdComic) -> Vec<(&'static str, String)> {
+    vec![
+        ("XKCD_NUM", comic.num.to_string()),
+        ("XKCD_TITLE", comic.title.clone()),
+        ("XKCD_SAFE_TITLE", comic.safe_title.clone()),
+        ("XKCD_ALT", comic.alt.clone()),
+        ("XKCD_IMG", comic.img.clone()),
+        ("XKCD_LINK", format!("https://xkcd.com/{}/", comic.num)),
+    ]
+}
+
+/// Executable files in `dir`, in name order like run-parts. A missing
+/// directory has no hooks.
+fn executables(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut hooks: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+        .map(|entry| entry.path())
+        .collect();
+    hooks.sort();
+    hooks
+}
+
+/// Runs every hook in `dir` one after another in the background, each
+/// with `env` added to its environment.
+pub fn run(dir: &Path, env: Vec<(&'static str, String)>) {
+    let hooks = executables(dir);
+    if hooks.is_empty() {
+        return;
+    }
+    thread::spawn(move || {
+        for hook in hooks {
+            // One failing hook must not keep the others from running
+            let _ = Command::new(hook)
+                .envs(env.iter().map(|(key, value)| (key, value)))
+                .stdin(Stdio::null())
+                .status();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executables() {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, mode) in [("20-notify", 0o755), ("10-log", 0o700), ("README", 0o644)] {
+            let path = dir.join(name);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        assert_eq!(
+            executables(&dir),
+            [dir.join("10-log"), dir.join("20-notify")]
+        );
+        assert!(executables(&dir.join("missing")).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use crate::clock::{Clock, SystemClock};
+use chrono::DateTime;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Certificate, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Largest response body buffered unless --max-response-size says otherwise.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static TLS: OnceLock<TlsConfig> = OnceLock::new();
+static MAX_BODY_SIZE: OnceLock<u64> = OnceLock::new();
+
+/// How long to pause after a 429 that doesn't say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Longest pause honored, however long a 429 asks for.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+/// The pause xkcd.com last asked for.
+static BACKOFF: Backoff = Backoff(Mutex::new(None));
+
+/// Until when requests are paused, if they are. Times come from a `Clock`
+/// so expiry can be tested without waiting.
+struct Backoff(Mutex<Option<Instant>>);
+
+impl Backoff {
+    fn pause(&self, wait: Duration, now: Instant) {
+        *self.0.lock().unwrap() = Some(now + wait);
+    }
+
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap()
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+/// Why a request didn't produce a usable body.
+#[derive(Debug)]
+pub enum Error {
+    Request(reqwest::Error),
+    Read(std::io::Error),
+    /// The server answered with something else, e.g. a captive portal's page.
+    UnexpectedType(String),
+    TooLarge(u64),
+    Json(serde_json::Error),
+    /// Not sent because the server asked us to wait this much longer.
+    RateLimited(Duration),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Request(e) => write!(f, "{}", e),
+            Error::Read(e) => write!(f, "reading response: {}", e),
+            Error::UnexpectedType(content_type) => {
+                write!(f, "unexpected content type {}", content_type)
+            }
+            Error::TooLarge(limit) => write!(f, "response larger than {} bytes", limit),
+            Error::Json(e) => write!(f, "malformed JSON: {}", e),
+            Error::RateLimited(wait) => {
+                write!(f, "rate limited for another {} seconds", wait.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(e) => Some(e),
+            Error::Read(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Request(e)
+    }
+}
+
+struct TlsConfig {
+    extra_certs: Vec<Certificate>,
+    accept_invalid_certs: bool,
+}
+
+/// The client shared by every request, so connections are reused.
+/// Responses may come gzip or brotli compressed, and HTTP/2 is used
+/// whenever the server offers it.
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| builder().build().expect("Failed to create HTTP client"))
+}
+
+/// Makes the shared client also trust the PEM certificates in `ca_bundle`
+/// and, for test mirrors only, accept any certificate. Has to be called
+/// before the first request.
+///
+/// The client itself is only created on first use: it starts a thread,
+/// which would not survive daemonizing.
+pub fn configure(ca_bundle: Option<&Path>, accept_invalid_certs: bool) -> Result<(), String> {
+    let extra_certs = match ca_bundle {
+        Some(path) => {
+            let pem = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Certificate::from_pem_bundle(&pem).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        None => Vec::new(),
+    };
+    let tls = TlsConfig {
+        extra_certs,
+        accept_invalid_certs,
+    };
+    TLS.set(tls)
+        .map_err(|_| "TLS is already configured".to_string())
+}
+
+fn builder() -> ClientBuilder {
+    let builder = Client::builder().gzip(true).brotli(true);
+    match TLS.get() {
+        Some(tls) => builder
+            .tls_certs_merge(tls.extra_certs.iter().cloned())
+            .tls_danger_accept_invalid_certs(tls.accept_invalid_certs),
+        None => builder,
+    }
+}
+
+/// Sends a request to xkcd.com or one of its mirrors, unless one of them
+/// recently answered 429 Too Many Requests. Such an answer pauses all of
+/// these requests for as long as its Retry-After header says.
+pub fn send(request: RequestBuilder) -> Result<Response, Error> {
+    if let Some(wait) = backoff_remaining(SystemClock.now()) {
+        return Err(Error::RateLimited(wait));
+    }
+    let response = request.send()?;
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return Ok(response);
+    }
+    let wait = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, SystemTime::now()))
+        .unwrap_or(DEFAULT_RETRY_AFTER);
+    BACKOFF.pause(wait, SystemClock.now());
+    Err(Error::RateLimited(wait))
+}
+
+/// How much longer requests are paused after a 429, if they are, as of
+/// `now`.
+pub fn backoff_remaining(now: Instant) -> Option<Duration> {
+    BACKOFF.remaining(now)
+}
+
+/// Reads a Retry-After value, either a number of seconds or an HTTP date,
+/// capped at `MAX_RETRY_AFTER`.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let wait = match value.trim().parse() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let at = DateTime::parse_from_rfc2822(value.trim()).ok()?.timestamp();
+            let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+            Duration::from_secs(at.saturating_sub(now).max(0) as u64)
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// Caps how much of any response is buffered. Has to be called before the
+/// first request.
+pub fn set_max_body_size(bytes: u64) {
+    let _ = MAX_BODY_SIZE.set(bytes);
+}
+
+/// Reads the whole body, provided the server says it is `content_type`
+/// (matched as a prefix, so `image/` accepts any image) and it fits within
+/// the size limit. The limit is enforced while reading, so a server that
+/// doesn't announce the length can't make us allocate more than that.
+pub fn read_body(response: Response, content_type: &str) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    copy_body(response, content_type, &mut body)?;
+    Ok(body)
+}
+
+/// Writes the whole body to `out` under the same conditions as
+/// `read_body`, returning its length. Whatever was written before the
+/// limit was hit stays in `out`.
+pub fn copy_body(
+    response: Response,
+    content_type: &str,
+    out: &mut impl Write,
+) -> Result<u64, Error> {
+    let limit = *MAX_BODY_SIZE.get_or_init(|| DEFAULT_MAX_BODY_SIZE);
+    check_response(
+        response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+        response.content_length(),
+        content_type,
+        limit,
+    )?;
+
+    let len = std::io::copy(&mut response.take(limit + 1), out).map_err(Error::Read)?;
+    if len > limit {
+        return Err(Error::TooLarge(limit));
+    }
+    Ok(len)
+}
+
+/// Reads a JSON body the same way as `read_body`.
+pub fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    serde_json::from_slice(&read_body(response, "application/json")?).map_err(Error::Json)
+}
+
+/// Checks the announced type and length. A missing type is let through.
+fn check_response(
+    actual_type: Option<&str>,
+    length: Option<u64>,
+    expected_type: &str,
+    limit: u64,
+) -> Result<(), Error> {
+    match actual_type {
+        Some(actual) if !actual.starts_with(expected_type) => {
+            Err(Error::UnexpectedType(actual.to_string()))
+        }
+        _ if length.is_some_and(|length| length > limit) => Err(Error::TooLarge(limit)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(1445412480); // 2015-10-21 07:28:00 UTC
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(
+            parse_retry_after("18446744073709551615", now),
+            Some(MAX_RETRY_AFTER)
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT", now),
+            Some(MAX_RETRY_AFTER)
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let clock = ManualClock::new();
+        let backoff = Backoff(Mutex::new(None));
+        assert_eq!(backoff.remaining(clock.now()), None);
+
+        backoff.pause(Duration::from_secs(60), clock.now());
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(backoff.remaining(clock.now()), Some(Duration::from_secs(1)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(backoff.remaining(clock.now()), None);
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(Some("image/png"), Some(100), "image/", 1000).is_ok());
+        assert!(check_response(None, None, "application/json", 1000).is_ok());
+        assert!(check_response(
+            Some("application/json; charset=utf-8"),
+            None,
+            "application/json",
+            1000
+        )
+        .is_ok());
+        assert!(matches!(
+            check_response(Some("text/html"), Some(100), "image/", 1000),
+            Err(Error::UnexpectedType(_))
+        ));
+        assert!(matches!(
+            check_response(Some("image/png"), Some(1001), "image/", 1000),
+            Err(Error::TooLarge(1000))
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+use fuser::SessionUnmounter;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// When the filesystem last handled a request.
+#[derive(Clone)]
+pub struct Activity(Arc<Mutex<Instant>>);
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl Activity {
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+/// Unmounts once no request has arrived for `timeout`, which makes
+/// `Session::run` return so the daemon exits. While files are still open
+/// the unmount fails, and it is tried again after another `timeout`.
+pub fn unmount_when_idle(timeout: Duration, activity: Activity, mut unmounter: SessionUnmounter) {
+    thread::spawn(move || loop {
+        let idle = activity.idle_for();
+        if idle < timeout {
+            thread::sleep(timeout - idle);
+        } else if unmounter.unmount().is_ok() {
+            break;
+        } else {
+            activity.touch();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity() {
+        let activity = Activity::default();
+        let shared = activity.clone();
+        thread::sleep(Duration::from_millis(20));
+        assert!(activity.idle_for() >= Duration::from_millis(20));
+        shared.touch();
+        assert!(activity.idle_for() < Duration::from_millis(20));
+    }
+}
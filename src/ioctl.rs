@@ -0,0 +1,59 @@
+//! ioctl commands understood on the root directory of a mount, so a running
+//! instance can be controlled without special files.
+//!
+//! The numbers follow the Linux `_IO`/`_IOR` encoding with type `'X'`.
+
+const TYPE: u32 = b'X' as u32;
+
+/// Checks for a new comic now instead of waiting for the next poll.
+pub const REFRESH: u32 = io(1);
+/// Drops the generated files cached in memory, such as `image-dark.png`.
+pub const DROP_CACHE: u32 = io(2);
+/// Fills in a `Stats`.
+pub const STATS: u32 = ior(3, std::mem::size_of::<Stats>() as u32);
+
+const fn io(nr: u32) -> u32 {
+    (TYPE << 8) | nr
+}
+
+const fn ior(nr: u32, size: u32) -> u32 {
+    // _IOC_READ in the top two bits, then a 14-bit argument size
+    (2 << 30) | (size << 16) | io(nr)
+}
+
+/// Reply to `STATS`, laid out as a C struct of native-endian `u32`s.
+#[repr(C)]
+pub struct Stats {
+    pub latest_num: u32,
+    /// Number of comics listed in index.txt.
+    pub comics: u32,
+}
+
+impl Stats {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.latest_num, self.comics]
+            .iter()
+            .flat_map(|field| field.to_ne_bytes())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(REFRESH, 0x5801);
+        assert_eq!(DROP_CACHE, 0x5802);
+        assert_eq!(STATS, 0x8008_5803);
+
+        let stats = Stats {
+            latest_num: 353,
+            comics: 350,
+        };
+        let bytes = stats.to_bytes();
+        assert_eq!(bytes.len(), std::mem::size_of::<Stats>());
+        assert_eq!(bytes[..4], 353u32.to_ne_bytes());
+    }
+}
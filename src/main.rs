@@ -1,64 +1,404 @@
-use clap::Parser;
-use fuser::MountOption;
-use serde::Deserialize;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use fuser::{MountOption, Session};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
+mod archive;
+mod clock;
+mod comic;
+mod dark;
+mod date;
+mod dimensions;
+mod doctor;
+mod error;
+mod events;
+mod explain;
 mod fs;
-use fs::XkcdFs;
+mod history;
+mod hooks;
+mod http;
+mod idle;
+mod ioctl;
+mod mirror;
+mod poller;
+mod range;
+mod sanitize;
+mod scrape;
+mod shutdown;
+mod slideshow;
+mod supervise;
+mod template;
+mod text;
+mod webhook;
+use events::EventLog;
+use fs::{Options, XkcdFs};
+use history::History;
+use idle::Activity;
+
+/// How eagerly to look for a newer comic than the one already loaded.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CachePolicy {
+    /// Check on every access
+    NoCache,
+    /// Only check when asked to through the refresh ioctl
+    PreferCache,
+    /// Check every --poll-interval seconds and after --max-stale
+    Ttl,
+}
+
+/// Tools that run instead of mounting.
+#[derive(Subcommand)]
+enum Commands {
+    /// Print a completion script for SHELL
+    Completions { shell: clap_complete::Shell },
+    /// Print the man page in roff format
+    Manpage,
+    /// Check FUSE, network and state directory setup and suggest fixes
+    Doctor,
+}
 
 #[derive(Parser)]
 #[command(name = "xkcdfs-fuse")]
 #[command(about = "XKCD FUSE filesystem")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Run in foreground
     #[arg(short, long)]
     foreground: bool,
 
-    /// Mountpoint path
-    mountpoint: std::path::PathBuf,
-}
+    /// Seconds between checks for a newly published comic
+    #[arg(long, default_value_t = 900)]
+    poll_interval: u64,
+
+    /// When to check for a new comic
+    #[arg(long, value_enum, default_value_t = CachePolicy::Ttl)]
+    cache_policy: CachePolicy,
+
+    /// Check for a new comic on the next access once the latest is this many seconds old,
+    /// while still answering from what is already loaded
+    #[arg(long, value_name = "SECONDS")]
+    max_stale: Option<u64>,
+
+    /// Only list and prefetch comics in this inclusive range, e.g. "2000..latest"
+    #[arg(long, value_name = "RANGE", value_parser = range::parse_range)]
+    comics: Option<range::ComicRange>,
+
+    /// POST a JSON payload (num, title, img, alt) to this URL for every new comic
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Also expose comic.url (Windows) and comic.webloc (macOS) link files
+    #[arg(long)]
+    interop_links: bool,
+
+    /// Wrap alt.txt at this many columns
+    #[arg(long, value_name = "COLUMNS")]
+    wrap_alt: Option<usize>,
+
+    /// Name per-comic files after a template, e.g. "{num:04}-{safe_title}-{name}"
+    #[arg(long, value_parser = template::parse_file_template)]
+    file_template: Option<String>,
+
+    /// Resolve file names regardless of case (listings keep their case)
+    #[arg(long)]
+    case_insensitive: bool,
+
+    /// Avoid file names Windows clients can't handle, for re-exporting via Samba
+    #[arg(long)]
+    windows_names: bool,
+
+    /// strftime format for date.txt [default: %Y-%m-%d]
+    #[arg(long, value_parser = date::parse_format)]
+    date_format: Option<String>,
+
+    /// Locale for month and day names in date.txt [default: from LC_TIME/LANG]
+    #[arg(long, value_parser = date::parse_locale)]
+    date_locale: Option<chrono::Locale>,
+
+    /// Add slideshow.html with this many of the most recent comics
+    #[arg(long, value_name = "COUNT")]
+    slideshow: Option<usize>,
+
+    /// Octal mask of permission bits to clear from files and directories
+    #[arg(long, value_parser = fs::parse_mask)]
+    umask: Option<u16>,
+
+    /// Octal mask for files, overriding --umask
+    #[arg(long, value_parser = fs::parse_mask)]
+    fmask: Option<u16>,
+
+    /// Octal mask for directories, overriding --umask
+    #[arg(long, value_parser = fs::parse_mask)]
+    dmask: Option<u16>,
+
+    /// Let the kernel enforce the reported permissions instead of the filesystem
+    #[arg(long)]
+    default_permissions: bool,
+
+    /// Owner of every file [default: the user mounting the filesystem]
+    #[arg(long)]
+    uid: Option<u32>,
+
+    /// Group of every file [default: the primary group of the user mounting]
+    #[arg(long)]
+    gid: Option<u32>,
+
+    /// Don't record which comics were viewed
+    #[arg(long)]
+    no_history: bool,
+
+    /// Unmount and exit after this many minutes without any filesystem activity
+    #[arg(long, value_name = "MINUTES")]
+    idle_timeout: Option<u64>,
+
+    /// Keep images larger than this in a temporary file instead of memory
+    #[arg(long, value_name = "BYTES")]
+    max_image_size: Option<u64>,
+
+    /// Give up on any download larger than this, e.g. a captive portal's endless page
+    #[arg(long, value_name = "BYTES", default_value_t = http::DEFAULT_MAX_BODY_SIZE)]
+    max_response_size: u64,
+
+    /// Fetch comic pages from this base URL, e.g. a local mirror; repeat to list
+    /// fallbacks in order [default: https://xkcd.com]
+    #[arg(long, value_name = "URL")]
+    mirror: Vec<String>,
+
+    /// Also trust the PEM certificates in this file, e.g. for an internal mirror's CA
+    #[arg(long, value_name = "FILE")]
+    ca_bundle: Option<std::path::PathBuf>,
+
+    /// Accept any TLS certificate, including self-signed ones. Only for test mirrors!
+    #[arg(long)]
+    insecure_tls: bool,
+
+    /// Mount right away and fetch the latest comic in the background, e.g. at boot
+    /// before the network is up
+    #[arg(long)]
+    no_startup_fetch: bool,
 
-#[derive(Deserialize)]
-struct XkcdComic {
-    title: String,
-    alt: String,
-    img: String,
+    /// Run the filesystem in a child process and remount it if that dies unexpectedly
+    #[arg(long)]
+    supervise: bool,
+
+    /// Mountpoint path
+    #[arg(required = true)]
+    mountpoint: Option<std::path::PathBuf>,
 }
 
 fn main() {
     let args = Args::parse();
 
+    match args.command {
+        Some(Commands::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                "xkcdfs-fuse",
+                &mut std::io::stdout(),
+            );
+            return;
+        }
+        Some(Commands::Manpage) => {
+            clap_mangen::Man::new(Args::command())
+                .render(&mut std::io::stdout())
+                .expect("Failed to write man page");
+            return;
+        }
+        Some(Commands::Doctor) => {
+            let healthy = doctor::run();
+            std::process::exit(if healthy { 0 } else { 1 });
+        }
+        None => {}
+    }
+
     // Resolve the absolute path of the mountpoint before we daemonize and chdir
-    let mountpoint = std::fs::canonicalize(&args.mountpoint).expect("Failed to resolve mountpoint");
+    let mountpoint = args
+        .mountpoint
+        .as_deref()
+        .expect("clap requires a mountpoint");
+    let mountpoint = std::fs::canonicalize(mountpoint).expect("Failed to resolve mountpoint");
+
+    if args.ca_bundle.is_some() || args.insecure_tls {
+        if let Err(e) = http::configure(args.ca_bundle.as_deref(), args.insecure_tls) {
+            eprintln!("Error setting up TLS: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    http::set_max_body_size(args.max_response_size);
+    if let Some(bytes) = args.max_image_size {
+        comic::set_max_image_size(bytes);
+    }
+    if !args.mirror.is_empty() {
+        mirror::configure(&args.mirror);
+    }
+
+    // The supervised child runs where we were started, so relative paths resolve the same
+    let cwd = std::env::current_dir().expect("Failed to resolve working directory");
 
     if !args.foreground {
         // Use libc::daemon to background the process.
         // First arg 0: change dir to /
-        // Second arg 0: redirect stdio to /dev/null
+        // Second arg: redirect stdio to /dev/null, unless supervising so crashes are logged
         unsafe {
-            if libc::daemon(0, 0) != 0 {
+            if libc::daemon(0, args.supervise as libc::c_int) != 0 {
                 eprintln!("Error daemonizing: {}", std::io::Error::last_os_error());
                 std::process::exit(1);
             }
         }
     }
 
-    let options = vec![MountOption::RO, MountOption::FSName("xkcdfs".to_string())];
+    if args.supervise {
+        supervise::run(&cwd, &mountpoint);
+    }
 
-    let comic: XkcdComic = reqwest::blocking::get("https://xkcd.com/info.0.json")
-        .expect("Failed to fetch latest comic info")
-        .json::<XkcdComic>()
-        .expect("Failed to parse comic info");
+    // Threads don't survive daemonizing, so this is the first one started
+    let unmounter = shutdown::handle_signals();
 
-    let image_bytes = reqwest::blocking::get(&comic.img)
-        .expect("Failed to fetch comic image")
-        .bytes()
-        .expect("Failed to read image bytes")
-        .to_vec();
+    let history = match History::default_path() {
+        Some(path) if !args.no_history => History::load(path),
+        _ => History::default(),
+    };
 
+    let mut options = vec![MountOption::RO, MountOption::FSName("xkcdfs".to_string())];
+    if args.default_permissions {
+        options.push(MountOption::DefaultPermissions);
+    }
+
+    // Without a startup fetch everything stays empty until the poller finds a comic
+    let comics = args.comics.unwrap_or_default();
+    let (comic, image, tags, archive) = if args.no_startup_fetch {
+        let image = comic::Image::Buffered(Vec::new());
+        (Default::default(), image, Vec::new(), Vec::new())
+    } else {
+        let comic = comic::fetch_latest_info().expect("Failed to fetch latest comic info");
+        let image = comic::fetch_image(&comic.img).expect("Failed to fetch comic image");
+        // Tags and the archive listing are extras, so mount without them if they fail
+        let tags = explain::fetch_tags(comic.num).unwrap_or_default();
+        let mut archive = archive::fetch_archive().unwrap_or_default();
+        archive.retain(|entry| comics.contains(entry.num));
+        (comic, image, tags, archive)
+    };
+    let (latest_img, latest_img_spooled) = image.into_parts();
+
+    // Comics that can't be fetched (such as 404) are left out of the slideshow
+    let recent = match args.slideshow {
+        Some(_) if args.no_startup_fetch => Vec::new(),
+        Some(count) => std::iter::once(comic.clone())
+            .filter(|latest| comics.contains(latest.num))
+            .chain(
+                (1..comic.num)
+                    .rev()
+                    .filter(|&num| comics.contains(num))
+                    .take(count.saturating_sub(1))
+                    .filter_map(|num| comic::fetch_info(num).ok()),
+            )
+            .take(count)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let events = Arc::new(Mutex::new(EventLog::default()));
+    events::expire_parked_reads(events.clone());
+    let (updates_tx, updates_rx) = mpsc::channel();
+    let (refresh_tx, refresh_rx) = mpsc::channel();
+    if args.no_startup_fetch {
+        let _ = refresh_tx.send(());
+    }
+    let latest_num = comic.num;
+    let latest_date = comic.date();
+
+    let activity = Activity::default();
     let fs = XkcdFs {
         latest_title: comic.title,
+        latest_safe_title: comic.safe_title,
         latest_alt: comic.alt,
-        latest_img: image_bytes,
+        latest_img,
+        latest_img_spooled,
+        latest_dark: Default::default(),
+        latest_sha256: Default::default(),
+        latest_num: comic.num,
+        latest_date,
+        latest_img_url: comic.img,
+        latest_link: comic.link,
+        latest_news: comic.news,
+        latest_tags: tags,
+        archive,
+        updated: SystemTime::now(),
+        recent,
+        events: events.clone(),
+        updates: updates_rx,
+        refresh: refresh_tx,
+        revalidated: Instant::now(),
+        stale: false,
+        history,
+        activity: activity.clone(),
+        open_handles: Default::default(),
+        lookups: Default::default(),
+        clock: Box::new(clock::SystemClock),
+        options: Options {
+            interop_links: args.interop_links,
+            alt_wrap: args.wrap_alt,
+            file_template: args.file_template,
+            case_insensitive: args.case_insensitive,
+            windows_names: args.windows_names,
+            date_format: args.date_format,
+            date_locale: Some(args.date_locale.unwrap_or_else(date::locale_from_env)),
+            file_mask: args.fmask.or(args.umask).unwrap_or(0),
+            dir_mask: args.dmask.or(args.umask).unwrap_or(0),
+            // getuid and getgid always succeed
+            uid: args.uid.unwrap_or_else(|| unsafe { libc::getuid() }),
+            gid: args.gid.unwrap_or_else(|| unsafe { libc::getgid() }),
+            comics,
+            max_stale: match args.cache_policy {
+                CachePolicy::NoCache => Some(Duration::ZERO),
+                CachePolicy::PreferCache => None,
+                CachePolicy::Ttl => args.max_stale.map(Duration::from_secs),
+            },
+            slideshow: args.slideshow,
+        },
     };
-    fuser::mount2(fs, mountpoint, &options).unwrap();
+    let mut session = Session::new(fs, mountpoint, &options).unwrap();
+
+    poller::spawn(
+        (args.cache_policy != CachePolicy::PreferCache)
+            .then(|| Duration::from_secs(args.poll_interval)),
+        latest_num,
+        updates_tx,
+        refresh_rx,
+        events,
+        session.notifier(),
+        args.webhook,
+    );
+
+    let _ = unmounter.send(session.unmount_callable());
+    if let Some(minutes) = args.idle_timeout {
+        idle::unmount_when_idle(
+            Duration::from_secs(minutes * 60),
+            activity,
+            session.unmount_callable(),
+        );
+    }
+
+    session.run().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+
+        assert!(Args::try_parse_from(["xkcdfs-fuse"]).is_err());
+        let args = Args::try_parse_from(["xkcdfs-fuse", "/mnt/xkcd"]).unwrap();
+        assert!(args.command.is_none());
+        let args = Args::try_parse_from(["xkcdfs-fuse", "completions", "bash"]).unwrap();
+        assert!(matches!(args.command, Some(Commands::Completions { .. })));
+    }
 }
@@ -0,0 +1,114 @@
+use crate::http;
+use reqwest::blocking::Response;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MIRROR: &str = "https://xkcd.com";
+
+/// How long a mirror that failed is only tried after the healthy ones.
+const COOLDOWN: Duration = Duration::from_secs(300);
+
+static MIRRORS: OnceLock<Vec<Mirror>> = OnceLock::new();
+
+struct Mirror {
+    base: String,
+    /// When the last request to this mirror failed, cleared by a success.
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Mirror {
+    fn new(base: &str) -> Self {
+        Mirror {
+            base: base.trim_end_matches('/').to_string(),
+            failed_at: Mutex::new(None),
+        }
+    }
+
+    fn healthy(&self, now: Instant) -> bool {
+        match *self.failed_at.lock().unwrap() {
+            Some(failed_at) => now.duration_since(failed_at) >= COOLDOWN,
+            None => true,
+        }
+    }
+}
+
+/// Sets the base URLs to fetch xkcd.com pages from, in order of preference.
+/// Has to be called before the first request; without it only xkcd.com
+/// itself is used.
+pub fn configure(bases: &[String]) {
+    let _ = MIRRORS.set(bases.iter().map(|base| Mirror::new(base)).collect());
+}
+
+/// Fetches `path`, e.g. `/info.0.json`, from the first mirror that answers.
+/// Mirrors that failed recently are tried last, so an unreachable one only
+/// slows down the first request after it goes away.
+///
+/// Unreachable mirrors and server errors fail over to the next mirror; the
+/// last mirror's error is returned if none of them work. Being rate limited
+/// pauses every mirror, so that is returned right away.
+pub fn get(path: &str) -> Result<Response, http::Error> {
+    let mirrors = MIRRORS.get_or_init(|| vec![Mirror::new(DEFAULT_MIRROR)]);
+    let mut last_error = None;
+    for mirror in by_preference(mirrors, Instant::now()) {
+        let response = http::send(http::client().get(format!("{}{}", mirror.base, path))).and_then(
+            |response| {
+                if response.status().is_server_error() {
+                    Ok(response.error_for_status()?)
+                } else {
+                    Ok(response)
+                }
+            },
+        );
+        match response {
+            Err(e @ http::Error::RateLimited(_)) => return Err(e),
+            Ok(response) => {
+                *mirror.failed_at.lock().unwrap() = None;
+                return Ok(response);
+            }
+            Err(e) => {
+                *mirror.failed_at.lock().unwrap() = Some(Instant::now());
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.expect("at least one mirror is configured"))
+}
+
+/// Healthy mirrors in configured order, followed by the ones still cooling
+/// down after a failure.
+fn by_preference(mirrors: &[Mirror], now: Instant) -> Vec<&Mirror> {
+    let (mut order, cooling): (Vec<_>, Vec<_>) =
+        mirrors.iter().partition(|mirror| mirror.healthy(now));
+    order.extend(cooling);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_by_preference() {
+        let mirrors = [
+            Mirror::new("http://mirror.local/"),
+            Mirror::new("https://xkcd.com"),
+        ];
+        let now = Instant::now();
+        let bases = |order: Vec<&Mirror>| order.iter().map(|m| m.base.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            bases(by_preference(&mirrors, now)),
+            ["http://mirror.local", "https://xkcd.com"]
+        );
+
+        *mirrors[0].failed_at.lock().unwrap() = Some(now);
+        assert_eq!(
+            bases(by_preference(&mirrors, now)),
+            ["https://xkcd.com", "http://mirror.local"]
+        );
+        assert_eq!(
+            bases(by_preference(&mirrors, now + COOLDOWN)),
+            ["http://mirror.local", "https://xkcd.com"]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use crate::archive;
+use crate::comic::{self, Comic};
+use crate::events::EventLog;
+use crate::explain;
+use crate::fs;
+use crate::hooks;
+use crate::http;
+use crate::webhook::{self, Payload};
+use fuser::Notifier;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How soon to try again while nothing is loaded or the last check failed.
+const RETRY: Duration = Duration::from_secs(30);
+
+/// What the poller tells the filesystem after each check.
+pub enum Update {
+    /// A newer comic, which also means the check worked.
+    New(Box<Comic>),
+    /// No newer comic, or `failed` to find out, in which case what the
+    /// filesystem shows may be outdated until a later check works.
+    Checked { failed: bool },
+}
+
+/// Checks xkcd.com for a comic newer than `latest_num` every `interval`, if
+/// set, and right away when something is sent on `refresh`. A `latest_num`
+/// of 0 means nothing was fetched at mount time, so the archive is fetched
+/// along with the first comic. While nothing is loaded or checks fail, for
+/// instance during a server outage, they are retried sooner.
+///
+/// The outcome of every check is handed to the filesystem through `updates`.
+/// New comics are also invalidated in the kernel through `notifier`,
+/// announced on `/.events`, posted to the `webhook` URL, if any, and passed
+/// to the new-comic hooks. The thread exits once the filesystem has been
+/// dropped.
+pub fn spawn(
+    interval: Option<Duration>,
+    mut latest_num: u32,
+    updates: Sender<Update>,
+    refresh: Receiver<()>,
+    events: Arc<Mutex<EventLog>>,
+    notifier: Notifier,
+    webhook: Option<String>,
+) {
+    let mut failing = false;
+    thread::spawn(move || loop {
+        let disconnected = match next_wait(interval, latest_num, failing) {
+            Some(interval) => refresh.recv_timeout(interval) == Err(RecvTimeoutError::Disconnected),
+            None => refresh.recv().is_err(),
+        };
+        if disconnected {
+            break;
+        }
+        // One check answers every refresh requested in the meantime
+        refresh.try_iter().for_each(drop);
+
+        let comic = match check(latest_num) {
+            Ok(Some(comic)) => comic,
+            result => {
+                failing = result.is_err();
+                if updates.send(Update::Checked { failed: failing }).is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        failing = false;
+
+        latest_num = comic.info.num;
+        let line = format!("{}\t{}\n", comic.info.num, comic.info.title);
+        let payload = Payload::from(&comic.info);
+        let hook_env = hooks::environment(&comic.info);
+        if updates.send(Update::New(Box::new(comic))).is_err() {
+            break;
+        }
+        fs::invalidate_latest(&notifier);
+        events.lock().unwrap().publish(&line);
+        if let Some(url) = &webhook {
+            // A failed delivery isn't retried; the next comic brings the next attempt
+            let _ = webhook::notify(url, &payload);
+        }
+        if let Some(dir) = hooks::new_comic_dir() {
+            hooks::run(&dir, hook_env);
+        }
+    });
+}
+
+/// Fetches the latest comic if it is newer than `latest_num`, along with
+/// everything the filesystem shows for it.
+fn check(latest_num: u32) -> Result<Option<Comic>, http::Error> {
+    let info = comic::fetch_latest_info()?;
+    if info.num <= latest_num {
+        return Ok(None);
+    }
+    let image = comic::fetch_image(&info.img)?;
+    // The archive and tags are optional, the comic is worth showing without them
+    let archive = match latest_num {
+        0 => Some(archive::fetch_archive().unwrap_or_default()),
+        _ => None,
+    };
+    let tags = explain::fetch_tags(info.num).unwrap_or_default();
+    Ok(Some(Comic {
+        info,
+        image,
+        tags,
+        archive,
+    }))
+}
+
+/// How long to wait for a refresh before checking anyway, `None` meaning
+/// only on request. Failed checks are retried sooner, except when checks
+/// only happen on request; the first comic is always fetched as soon as
+/// possible.
+fn next_wait(interval: Option<Duration>, latest_num: u32, failing: bool) -> Option<Duration> {
+    match interval {
+        _ if latest_num == 0 => Some(interval.map_or(RETRY, |i| i.min(RETRY))),
+        Some(interval) if failing => Some(interval.min(RETRY)),
+        interval => interval,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_wait() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(next_wait(Some(hour), 353, false), Some(hour));
+        assert_eq!(next_wait(None, 353, false), None);
+        assert_eq!(next_wait(Some(hour), 0, false), Some(RETRY));
+        assert_eq!(next_wait(None, 0, false), Some(RETRY));
+        assert_eq!(next_wait(Some(hour), 353, true), Some(RETRY));
+        // With prefer-cache a failed check waits for the next request
+        assert_eq!(next_wait(None, 353, true), None);
+        assert_eq!(
+            next_wait(Some(Duration::from_secs(5)), 0, false),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
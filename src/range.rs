@@ -0,0 +1,69 @@
+/// An inclusive range of comic numbers such as `2000..latest`, limiting
+/// which comics are listed and prefetched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComicRange {
+    pub first: u32,
+    /// Last comic in the range, or `None` to follow the latest comic.
+    pub last: Option<u32>,
+}
+
+impl ComicRange {
+    pub fn contains(&self, num: u32) -> bool {
+        num >= self.first && self.last.is_none_or(|last| num <= last)
+    }
+}
+
+/// Parses `FIRST..LAST`, where either end may be left out and LAST may be
+/// `latest`, or a single comic number.
+pub fn parse_range(range: &str) -> Result<ComicRange, String> {
+    let parse_num = |num: &str| {
+        num.parse::<u32>()
+            .map_err(|_| format!("{} is not a comic number", num))
+    };
+    let Some((first, last)) = range.split_once("..") else {
+        let num = parse_num(range)?;
+        return Ok(ComicRange {
+            first: num,
+            last: Some(num),
+        });
+    };
+    let first = match first {
+        "" => 0,
+        first => parse_num(first)?,
+    };
+    let last = match last {
+        "" | "latest" => None,
+        last => Some(parse_num(last)?),
+    };
+    if last.is_some_and(|last| last < first) {
+        return Err(format!("{} ends before it starts", range));
+    }
+    Ok(ComicRange { first, last })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let recent = parse_range("2000..latest").unwrap();
+        assert_eq!(recent, parse_range("2000..").unwrap());
+        assert!(!recent.contains(1999));
+        assert!(recent.contains(2000));
+        assert!(recent.contains(u32::MAX));
+
+        let early = parse_range("..100").unwrap();
+        assert!(early.contains(1));
+        assert!(early.contains(100));
+        assert!(!early.contains(101));
+
+        let python = parse_range("353").unwrap();
+        assert!(python.contains(353));
+        assert!(!python.contains(354));
+
+        assert!(ComicRange::default().contains(1));
+        assert!(parse_range("200..100").is_err());
+        assert!(parse_range("1..newest").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest file name most Linux filesystems (and FUSE) accept, in bytes.
+const NAME_MAX: usize = 255;
+
+/// Turns a comic title into text that can be embedded in a file name:
+/// NFC-normalized, with `/` replaced and NUL and control characters removed.
+pub fn title_component(title: &str) -> String {
+    title
+        .nfc()
+        .filter_map(|c| match c {
+            '/' => Some('_'),
+            '\t' | '\n' | '\r' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Makes a complete file name valid: never empty, `.` or `..`, and no longer
+/// than NAME_MAX bytes (cut on a character boundary).
+pub fn file_name(mut name: String) -> String {
+    if name.is_empty() || name == "." || name == ".." {
+        name = name.replace('.', "_");
+        name.insert(0, '_');
+    }
+    if name.len() > NAME_MAX {
+        let mut end = NAME_MAX;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    name
+}
+
+/// Device names Windows refuses as file names, with or without extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Additionally makes a file name acceptable to Windows clients: replaces
+/// `\ : * ? " < > |`, trailing dots and spaces, and prefixes reserved
+/// device names such as `CON` or `lpt1.txt` with an underscore.
+pub fn windows_name(name: String) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| match c {
+            '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        name.insert(0, '_');
+    }
+
+    // Truncating first, so it can't leave a new trailing dot or space
+    let mut name = file_name(name);
+    let kept = name.trim_end_matches(['.', ' ']).len();
+    if kept < name.len() {
+        let replaced = "_".repeat(name.len() - kept);
+        name.truncate(kept);
+        name.push_str(&replaced);
+    }
+    name
+}
+
+/// Renames later duplicates to `stem~2.ext`, `stem~3.ext`, … so every name
+/// in the list is unique. Earlier entries keep their name, which makes the
+/// result deterministic for a given order.
+pub fn disambiguate(names: &mut [String]) {
+    let mut seen = HashSet::new();
+    for name in names.iter_mut() {
+        if seen.insert(name.clone()) {
+            continue;
+        }
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+            _ => (name.clone(), String::new()),
+        };
+        let mut n = 2;
+        loop {
+            let suffix = format!("~{}{}", n, ext);
+            let mut candidate = file_name(stem.clone());
+            let mut end = NAME_MAX.saturating_sub(suffix.len()).min(candidate.len());
+            while !candidate.is_char_boundary(end) {
+                end -= 1;
+            }
+            candidate.truncate(end);
+            candidate.push_str(&suffix);
+            if seen.insert(candidate.clone()) {
+                *name = candidate;
+                break;
+            }
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sample of archive titles with the characters that need care.
+    const TITLES: &[&str] = &[
+        "Barrel - Part 1",
+        "Python",
+        "Clich\u{0065}\u{0301}d Exchanges",
+        "Exploits of a Mom",
+        "Tic Tac Toe/Noughts and Crosses",
+        "I'm So Random",
+        "Time",
+        "Mu\u{0000}sic\u{0007}",
+        "  ..  ",
+        "Donald Knuth: \"Premature optimization\"",
+        "\u{1F409} Dragons",
+    ];
+
+    #[test]
+    fn test_title_component() {
+        for title in TITLES {
+            let component = title_component(title);
+            assert!(!component.contains('/'), "{:?}", component);
+            assert!(!component.chars().any(char::is_control), "{:?}", component);
+            assert_eq!(component, component.nfc().collect::<String>());
+        }
+
+        assert_eq!(title_component("Clich\u{0065}\u{0301}d"), "Clich\u{00e9}d");
+        assert_eq!(
+            title_component("Tic Tac Toe/Noughts"),
+            "Tic Tac Toe_Noughts"
+        );
+        assert_eq!(title_component("Mu\u{0000}sic\u{0007}"), "Music");
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(String::new()), "_");
+        assert_eq!(file_name(".".to_string()), "__");
+        assert_eq!(file_name("..".to_string()), "___");
+        assert_eq!(file_name("é".repeat(200)).len(), 254);
+        for title in TITLES {
+            let name = file_name(title_component(title));
+            assert!(!name.is_empty() && name != "." && name != "..");
+        }
+    }
+
+    #[test]
+    fn test_windows_name() {
+        assert_eq!(
+            windows_name("a\\b:c*d?e\"f<g>h|i".to_string()),
+            "a_b_c_d_e_f_g_h_i"
+        );
+        assert_eq!(windows_name("Why?. ".to_string()), "Why___");
+        assert_eq!(windows_name("con".to_string()), "_con");
+        assert_eq!(windows_name("LPT1.txt".to_string()), "_LPT1.txt");
+        assert_eq!(windows_name("Console.txt".to_string()), "Console.txt");
+        assert_eq!(windows_name("com0.txt".to_string()), "_com0.txt");
+        assert_eq!(windows_name("CONIN$".to_string()), "_CONIN$");
+
+        // A dot or space that truncation leaves at the end is replaced too
+        for end in [".", " "] {
+            let name = windows_name(format!("{}{}tail", "a".repeat(254), end));
+            assert_eq!(name.len(), NAME_MAX);
+            assert!(name.ends_with("a_"));
+        }
+        let name = windows_name(format!("con.{}", "a".repeat(300)));
+        assert_eq!(name.len(), NAME_MAX);
+        assert!(name.starts_with("_con."));
+        for title in TITLES {
+            let name = windows_name(file_name(title_component(title)));
+            assert!(!name.contains(['\\', ':', '*', '?', '"', '<', '>', '|']));
+            assert!(!name.ends_with(['.', ' ']));
+        }
+    }
+
+    #[test]
+    fn test_disambiguate() {
+        let mut names = vec![
+            "a.png".to_string(),
+            "b.txt".to_string(),
+            "a.png".to_string(),
+            "a~2.png".to_string(),
+            "a.png".to_string(),
+        ];
+        disambiguate(&mut names);
+        assert_eq!(names, ["a.png", "b.txt", "a~2.png", "a~2~2.png", "a~3.png"]);
+    }
+}
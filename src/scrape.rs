@@ -0,0 +1,67 @@
+use crate::archive::unescape_html;
+use crate::comic::XkcdComic;
+
+/// Reads a comic's metadata off its HTML page, for when the JSON API is
+/// failing. The page has no date or news, so those are left empty, and the
+/// title doubles as the safe title.
+pub fn parse_comic_page(html: &str) -> Option<XkcdComic> {
+    let (_, rest) = html.split_once("<div id=\"ctitle\">")?;
+    let (title, rest) = rest.split_once("</div>")?;
+    let (_, rest) = rest.split_once("<div id=\"comic\">")?;
+    let (_, rest) = rest.split_once("<img")?;
+    let (img_tag, rest) = rest.split_once('>')?;
+    let (_, rest) = rest.split_once("Permanent link to this comic: ")?;
+    let (_, rest) = rest.split_once("xkcd.com/")?;
+    let num = rest
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+
+    let img = attribute(img_tag, "src")?;
+    let img = match img.strip_prefix("//") {
+        Some(host) => format!("https://{}", host),
+        None => img.to_string(),
+    };
+    let title = unescape_html(title.trim());
+
+    Some(XkcdComic {
+        num,
+        safe_title: title.clone(),
+        title,
+        alt: unescape_html(attribute(img_tag, "title").unwrap_or_default()),
+        img,
+        ..Default::default()
+    })
+}
+
+/// The raw value of a double-quoted attribute inside a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let (_, rest) = tag.split_once(&format!(" {}=\"", name))?;
+    Some(rest.split_once('"')?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comic_page() {
+        let html = r#"<div id="ctitle">Python</div>
+<ul class="comicNav"><li><a href="/1/">|&lt;</a></li></ul>
+<div id="comic">
+<img src="//imgs.xkcd.com/comics/python.png" title="Perl, I&#39;m leaving you." alt="Python" style="image-orientation:none" />
+</div>
+<br />
+Permanent link to this comic: <a href="https://xkcd.com/353">https://xkcd.com/353/</a><br />
+"#;
+        let comic = parse_comic_page(html).unwrap();
+        assert_eq!(comic.num, 353);
+        assert_eq!(comic.title, "Python");
+        assert_eq!(comic.alt, "Perl, I'm leaving you.");
+        assert_eq!(comic.img, "https://imgs.xkcd.com/comics/python.png");
+        assert!(comic.date().is_none());
+
+        assert!(parse_comic_page("<html>Captive portal</html>").is_none());
+    }
+}
@@ -0,0 +1,57 @@
+use fuser::SessionUnmounter;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Signals that unmount the filesystem instead of killing the daemon and
+/// leaving a dead mountpoint behind.
+const SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+
+/// Blocks the shutdown signals in the calling thread and every thread it
+/// starts afterwards, returning the set to `sigwait` on.
+pub fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    }
+}
+
+/// Takes over the shutdown signals. Has to be called before any other
+/// thread is started, since threads inherit the blocked signals from the
+/// thread starting them.
+///
+/// Once the filesystem is mounted, send its unmounter on the returned
+/// channel: a signal then unmounts, which makes `Session::run` return so
+/// the daemon exits normally. A signal before that exits right away, as
+/// there is nothing to clean up yet. So does a signal whose unmount fails,
+/// e.g. because a file is still open, and any signal after the first, so
+/// the daemon can always be stopped.
+pub fn handle_signals() -> Sender<SessionUnmounter> {
+    let set = block_signals();
+
+    let (unmounter_tx, unmounter_rx) = mpsc::channel::<SessionUnmounter>();
+    thread::spawn(move || {
+        let mut unmounting = false;
+        loop {
+            let mut signal = 0;
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                return;
+            }
+            if unmounting {
+                std::process::exit(128 + signal);
+            }
+            unmounting = true;
+            let unmounted = unmounter_rx
+                .try_recv()
+                .is_ok_and(|mut unmounter| unmounter.unmount().is_ok());
+            if !unmounted {
+                std::process::exit(128 + signal);
+            }
+        }
+    });
+    unmounter_tx
+}
@@ -0,0 +1,100 @@
+use crate::comic::XkcdComic;
+
+/// Renders `comics`, newest first, as a self-contained HTML page with one
+/// slide per comic. Slides link to their neighbours, so it works without
+/// JavaScript; the arrow keys move between slides too.
+pub fn render(comics: &[XkcdComic]) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>xkcd: recent comics</title>\n<style>\n",
+        "body { margin: 0; font-family: sans-serif; text-align: center; }\n",
+        "section { min-height: 100vh; padding: 1em; box-sizing: border-box; }\n",
+        "img { max-width: 100%; }\n",
+        "</style>\n</head>\n<body>\n"
+    ));
+    for (i, comic) in comics.iter().enumerate() {
+        html.push_str(&format!(
+            "<section id=\"{}\">\n<h1><a href=\"https://xkcd.com/{}/\">{}: {}</a></h1>\n",
+            comic.num,
+            comic.num,
+            comic.num,
+            escape_html(&comic.title)
+        ));
+        html.push_str(&format!(
+            "<img src=\"{}\" alt=\"{}\" title=\"{}\">\n<p>{}</p>\n<nav>",
+            escape_html(&comic.img),
+            escape_html(&comic.title),
+            escape_html(&comic.alt),
+            escape_html(&comic.alt)
+        ));
+        if let Some(newer) = i.checked_sub(1).map(|i| &comics[i]) {
+            html.push_str(&format!(
+                "<a href=\"#{}\" rel=\"prev\">Newer</a> ",
+                newer.num
+            ));
+        }
+        if let Some(older) = comics.get(i + 1) {
+            html.push_str(&format!(
+                "<a href=\"#{}\" rel=\"next\">Older</a>",
+                older.num
+            ));
+        }
+        html.push_str("</nav>\n</section>\n");
+    }
+    html.push_str(concat!(
+        "<script>\ndocument.addEventListener(\"keydown\", (e) => {\n",
+        "  const rel = { ArrowLeft: \"prev\", ArrowRight: \"next\" }[e.key];\n",
+        "  const current = document.getElementById(location.hash.slice(1))\n",
+        "    || document.querySelector(\"section\");\n",
+        "  const link = rel && current && current.querySelector(`a[rel=${rel}]`);\n",
+        "  if (link) link.click();\n});\n</script>\n</body>\n</html>\n"
+    ));
+    html
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic(num: u32, title: &str, alt: &str) -> XkcdComic {
+        XkcdComic {
+            num,
+            title: title.to_string(),
+            safe_title: title.to_string(),
+            alt: alt.to_string(),
+            img: format!("https://imgs.xkcd.com/comics/{}.png", num),
+            link: String::new(),
+            news: String::new(),
+            year: "2007".to_string(),
+            month: "12".to_string(),
+            day: "5".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let html = render(&[
+            comic(354, "Reverse Euphemisms", "Alt"),
+            comic(
+                353,
+                "Python",
+                "I wrote 20 short programs in Python yesterday. <3",
+            ),
+        ]);
+
+        let newer = html.find("<section id=\"354\">").unwrap();
+        let older = html.find("<section id=\"353\">").unwrap();
+        assert!(newer < older);
+        assert!(html.contains("<a href=\"#353\" rel=\"next\">Older</a>"));
+        assert!(html.contains("<a href=\"#354\" rel=\"prev\">Newer</a>"));
+        assert!(html.contains("title=\"I wrote 20 short programs in Python yesterday. &lt;3\""));
+        assert!(!html.contains("<3"));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::shutdown;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest wait between restarts, and how long a filesystem process has to
+/// run before it counts as having started successfully.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Runs the filesystem in a child process and mounts it again whenever the
+/// child dies unexpectedly. Returns only by exiting, once the child exits
+/// cleanly after being unmounted.
+///
+/// The child gets the same arguments, minus `--supervise`, and runs in
+/// `cwd` so relative paths keep working. Shutdown signals are forwarded to
+/// it.
+pub fn run(cwd: &Path, mountpoint: &Path) -> ! {
+    let child_pid = Arc::new(AtomicI32::new(0));
+    let stopping = Arc::new(AtomicBool::new(false));
+    forward_signals(child_pid.clone(), stopping.clone());
+
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("Error finding the xkcdfs-fuse executable: {}", e);
+        std::process::exit(1);
+    });
+    let args = child_args(std::env::args_os().skip(1));
+
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let started = Instant::now();
+        let mut child = match Command::new(&exe).args(&args).current_dir(cwd).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Error starting the filesystem process: {}", e);
+                std::process::exit(1);
+            }
+        };
+        child_pid.store(child.id() as i32, Ordering::SeqCst);
+        let status = child.wait();
+        child_pid.store(0, Ordering::SeqCst);
+
+        match status {
+            Ok(status) if status.success() => std::process::exit(0),
+            _ if stopping.load(Ordering::SeqCst) => std::process::exit(0),
+            Ok(status) => eprintln!("Filesystem process died ({}), remounting", status),
+            Err(e) => eprintln!("Lost track of the filesystem process ({}), remounting", e),
+        }
+
+        clear_dead_mount(mountpoint);
+        if started.elapsed() >= MAX_BACKOFF {
+            backoff = Duration::from_secs(1);
+        }
+        thread::sleep(backoff);
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Arguments for the child: the supervisor's own, without `--supervise`
+/// and always in the foreground.
+fn child_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.filter(|arg| arg != "--supervise").collect();
+    if !args.iter().any(|arg| arg == "-f" || arg == "--foreground") {
+        args.insert(0, "--foreground".into());
+    }
+    args
+}
+
+/// Passes shutdown signals on to the child, or exits if there is none.
+fn forward_signals(child_pid: Arc<AtomicI32>, stopping: Arc<AtomicBool>) {
+    let set = shutdown::block_signals();
+    thread::spawn(move || loop {
+        let mut signal = 0;
+        if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+            return;
+        }
+        stopping.store(true, Ordering::SeqCst);
+        match child_pid.load(Ordering::SeqCst) {
+            0 => std::process::exit(128 + signal),
+            pid => unsafe {
+                libc::kill(pid, signal);
+            },
+        }
+    });
+}
+
+/// A crashed FUSE process leaves its mountpoint behind, failing with
+/// "Transport endpoint is not connected" until it is unmounted.
+fn clear_dead_mount(mountpoint: &Path) {
+    for fusermount in ["fusermount3", "fusermount"] {
+        let status = Command::new(fusermount)
+            .arg("-u")
+            .arg("-z")
+            .arg(mountpoint)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if status.is_ok() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_args() {
+        let args = |args: &[&str]| child_args(args.iter().map(OsString::from));
+        assert_eq!(
+            args(&["--supervise", "--poll-interval", "60", "/mnt/xkcd"]),
+            ["--foreground", "--poll-interval", "60", "/mnt/xkcd"]
+        );
+        assert_eq!(
+            args(&["-f", "--supervise", "/mnt/xkcd"]),
+            ["-f", "/mnt/xkcd"]
+        );
+    }
+}
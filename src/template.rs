@@ -0,0 +1,118 @@
+use crate::sanitize::title_component;
+
+/// Widest `{num:0N}` padding accepted; a u32 never needs more digits.
+const MAX_NUM_WIDTH: usize = 10;
+
+/// Values available to file name templates.
+pub struct TemplateVars<'a> {
+    pub num: u32,
+    pub title: &'a str,
+    pub safe_title: &'a str,
+    /// The file's default name, e.g. `image.png`.
+    pub name: &'a str,
+}
+
+/// Checks that a per-comic file template keeps every file distinct, which
+/// means it has to include the default name, that it names a file rather
+/// than a path, and that `{num}` padding stays within what a comic number
+/// can use.
+pub fn parse_file_template(template: &str) -> Result<String, String> {
+    if !template.contains("{name}") {
+        return Err("the template must contain {name} to keep file names unique".to_string());
+    }
+    // Placeholders never expand to a `/`, so only the literal text can add one
+    if template.contains('/') {
+        return Err("the template can't contain /, file names can't include it".to_string());
+    }
+    for (start, _) in template.match_indices("{num:") {
+        let format = template[start + 5..].split('}').next().unwrap_or_default();
+        if format.starts_with('0') && num_width(format).is_none() {
+            return Err(format!(
+                "{{num:{}}}: comic numbers are padded to at most {} digits",
+                format, MAX_NUM_WIDTH
+            ));
+        }
+    }
+    Ok(template.to_string())
+}
+
+/// The zero-padded width of a `{num:0N}` format, if it is one.
+fn num_width(format: &str) -> Option<usize> {
+    let width = format.strip_prefix('0')?;
+    if width.is_empty() || !width.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    width.parse().ok().filter(|&width| width <= MAX_NUM_WIDTH)
+}
+
+/// Expands `{num}`, `{title}`, `{safe_title}`, `{name}`, `{stem}` and
+/// `{ext}` placeholders. `{num}` accepts a zero-padded width such as
+/// `{num:04}`. Unknown placeholders are left as they are.
+pub fn render(template: &str, vars: &TemplateVars) -> String {
+    let (stem, ext) = vars.name.rsplit_once('.').unwrap_or((vars.name, ""));
+
+    let mut out = String::with_capacity(template.len() + vars.name.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let (key, format) = rest[1..end].split_once(':').unwrap_or((&rest[1..end], ""));
+        match (key, format) {
+            ("num", "") => out.push_str(&vars.num.to_string()),
+            ("num", format) if num_width(format).is_some() => {
+                let width = num_width(format).unwrap_or(0);
+                out.push_str(&format!("{:0width$}", vars.num, width = width));
+            }
+            ("title", "") => out.push_str(&title_component(vars.title)),
+            ("safe_title", "") => out.push_str(&title_component(vars.safe_title)),
+            ("name", "") => out.push_str(vars.name),
+            ("stem", "") => out.push_str(stem),
+            ("ext", "") => out.push_str(ext),
+            _ => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = TemplateVars {
+            num: 353,
+            title: "Python/Perl",
+            safe_title: "Python",
+            name: "image.png",
+        };
+
+        assert_eq!(
+            render("{num:05}-{safe_title}.{ext}", &vars),
+            "00353-Python.png"
+        );
+        assert_eq!(
+            render("{num} {title} {name}", &vars),
+            "353 Python_Perl image.png"
+        );
+        assert_eq!(
+            render("{stem}-{num:4}{unknown}", &vars),
+            "image-{num:4}{unknown}"
+        );
+        assert_eq!(render("{name} {", &vars), "image.png {");
+
+        assert!(parse_file_template("{num}-{name}").is_ok());
+        assert!(parse_file_template("{num}.{ext}").is_err());
+        assert!(parse_file_template("{num}/{name}").is_err());
+        assert!(parse_file_template("{num}-{name}/").is_err());
+        assert!(parse_file_template("{num:010}-{name}").is_ok());
+        assert!(parse_file_template("{num:011}-{name}").is_err());
+        assert!(parse_file_template("{num:099999999999999999999}-{name}").is_err());
+        assert_eq!(render("{num:070000}", &vars), "{num:070000}");
+    }
+}
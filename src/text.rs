@@ -0,0 +1,44 @@
+/// Greedily wraps `text` so no line exceeds `width` characters, breaking only
+/// at spaces. Existing line breaks are kept and words longer than `width`
+/// are left on a line of their own.
+pub fn wrap(text: &str, width: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let mut line_len = 0;
+        for word in line.split(' ').filter(|word| !word.is_empty()) {
+            let word_len = word.chars().count();
+            if line_len > 0 && line_len + 1 + word_len > width {
+                out.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                out.push(' ');
+                line_len += 1;
+            }
+            out.push_str(word);
+            line_len += word_len;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("I wrote 20 short programs in Python yesterday.", 16),
+            "I wrote 20 short\nprograms in\nPython\nyesterday."
+        );
+        assert_eq!(wrap("a\nb c", 1), "a\nb\nc");
+        assert_eq!(
+            wrap("supercalifragilistic word", 5),
+            "supercalifragilistic\nword"
+        );
+        assert_eq!(wrap("", 10), "");
+    }
+}
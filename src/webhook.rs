@@ -0,0 +1,33 @@
+use crate::comic::XkcdComic;
+use crate::http;
+use serde::Serialize;
+
+/// JSON body posted to the --webhook URL for every new comic.
+#[derive(Serialize)]
+pub struct Payload {
+    num: u32,
+    title: String,
+    img: String,
+    alt: String,
+}
+
+impl From<&XkcdComic> for Payload {
+    fn from(comic: &XkcdComic) -> Self {
+        Payload {
+            num: comic.num,
+            title: comic.title.clone(),
+            img: comic.img.clone(),
+            alt: comic.alt.clone(),
+        }
+    }
+}
+
+/// Posts `payload` to `url`, failing on error statuses too.
+pub fn notify(url: &str, payload: &Payload) -> reqwest::Result<()> {
+    http::client()
+        .post(url)
+        .json(payload)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}